@@ -0,0 +1,233 @@
+// Detect when a sequence of states starts repeating, so that we can jump
+// straight to the state at some far off generation without computing every
+// one in between. States can optionally have a translation component (eg an
+// offset along a line) which is ignored when comparing them; if a state repeats
+// but has moved, the cycle is said to drift by that much each period.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Generations up to this far are worked out by stepping to them directly,
+// rather than looking for a cycle first (which might never be found):
+const DIRECT_STEPS: usize = 1000;
+
+// ### Something that we can look for cycles in ###
+pub trait State: Clone {
+    // Two states with the same key are considered to be the same state,
+    // regardless of any translation between them:
+    type Key: Hash + Eq;
+    fn key(&self) -> Self::Key;
+    // How far this state is translated (if it can be):
+    fn offset(&self) -> i64 {
+        0
+    }
+    // This state, translated by some amount:
+    fn translate(&self, _by: i64) -> Self {
+        self.clone()
+    }
+}
+
+// ### A repeating cycle found in some sequence ###
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Cycle {
+    // The first generation that is part of the cycle:
+    pub start: usize,
+    // How many generations until the state repeats:
+    pub period: usize,
+    // How far the state is translated each time it repeats:
+    pub drift: i64
+}
+
+// ### How to look for a cycle ###
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Method {
+    // Remember the key of every state seen until one repeats. This steps
+    // through the fewest states, but needs memory for each key.
+    Hashing,
+    // Brent's algorithm; only a couple of states are kept around at once, at
+    // the cost of stepping through a few more of them.
+    Brent
+}
+
+// ### A sequence of states, each produced from the last by some step function ###
+//
+// Only the initial state is kept; any other state is recomputed from it when
+// asked for, so memory use is just whatever the cycle finding method needs.
+pub struct Sequence<S: State, F> {
+    step: F,
+    initial: S,
+    method: Method,
+    cycle: Option<Cycle>,
+    // The furthest generation that we've stepped to so far:
+    stepped: usize
+}
+impl <S: State, F: FnMut(&S) -> S> Sequence<S,F> {
+    pub fn new(initial: S, step: F) -> Sequence<S,F> {
+        Sequence::with_method(initial, step, Method::Hashing)
+    }
+    pub fn with_method(initial: S, step: F, method: Method) -> Sequence<S,F> {
+        Sequence {
+            step,
+            initial,
+            method,
+            cycle: None,
+            stepped: 0
+        }
+    }
+    // Step through states until one repeats, and return the cycle found.
+    // This will never return if the sequence doesn't cycle.
+    pub fn find_cycle(&mut self) -> Cycle {
+        if let Some(cycle) = self.cycle {
+            return cycle;
+        }
+        let cycle = match self.method {
+            Method::Hashing => self.find_cycle_hashing(),
+            Method::Brent => self.find_cycle_brent()
+        };
+        self.cycle = Some(cycle);
+        cycle
+    }
+    // The state at any generation, using the cycle to skip ahead if we need to.
+    // Nearby generations (or ones we've already stepped past) are stepped to
+    // directly, but as with `find_cycle`, asking for a far off generation
+    // won't return if the sequence doesn't cycle.
+    pub fn state_at(&mut self, n: usize) -> S {
+        if self.cycle.is_none() && n <= self.stepped.max(DIRECT_STEPS) {
+            return self.step_from_initial(n);
+        }
+        let Cycle { start, period, drift } = self.find_cycle();
+        if n < start + period {
+            return self.step_from_initial(n);
+        }
+        let periods = (n - start) / period;
+        let idx = start + (n - start) % period;
+        self.step_from_initial(idx).translate(periods as i64 * drift)
+    }
+    fn find_cycle_hashing(&mut self) -> Cycle {
+        let mut seen = HashMap::new();
+        let mut state = self.initial.clone();
+        for idx in 0.. {
+            let key = state.key();
+            match seen.get(&key).cloned() {
+                Some((fst, offset)) => {
+                    return Cycle { start: fst, period: idx - fst, drift: state.offset() - offset };
+                },
+                None => {
+                    seen.insert(key, (idx, state.offset()));
+                    state = (self.step)(&state);
+                }
+            }
+        }
+        unreachable!()
+    }
+    fn find_cycle_brent(&mut self) -> Cycle {
+        // Find the period by having the tortoise teleport to the hare
+        // every power of two steps, until the hare catches up with it:
+        let mut power = 1;
+        let mut period = 1;
+        let mut tortoise = self.initial.clone();
+        let mut tortoise_key = tortoise.key();
+        let mut hare = (self.step)(&tortoise);
+        while hare.key() != tortoise_key {
+            if power == period {
+                tortoise = hare.clone();
+                tortoise_key = tortoise.key();
+                power *= 2;
+                period = 0;
+            }
+            hare = (self.step)(&hare);
+            period += 1;
+        }
+
+        // Find the start by moving both at the same speed, one period apart:
+        let mut start = 0;
+        let mut tortoise = self.initial.clone();
+        let mut hare = self.step_from_initial(period);
+        while hare.key() != tortoise.key() {
+            tortoise = (self.step)(&tortoise);
+            hare = (self.step)(&hare);
+            start += 1;
+        }
+        Cycle { start, period, drift: hare.offset() - tortoise.offset() }
+    }
+    fn step_from_initial(&mut self, n: usize) -> S {
+        let mut state = self.initial.clone();
+        for _ in 0..n {
+            state = (self.step)(&state);
+        }
+        self.stepped = self.stepped.max(n);
+        state
+    }
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Plain numbers, which never move:
+    impl State for u64 {
+        type Key = u64;
+        fn key(&self) -> u64 {
+            *self
+        }
+    }
+
+    // A pattern that moves along a line as it repeats:
+    #[derive(Clone,Debug,PartialEq)]
+    struct Glider { shape: u8, offset: i64 }
+    impl State for Glider {
+        type Key = u8;
+        fn key(&self) -> u8 { self.shape }
+        fn offset(&self) -> i64 { self.offset }
+        fn translate(&self, by: i64) -> Glider {
+            Glider { shape: self.shape, offset: self.offset + by }
+        }
+    }
+
+    #[test]
+    fn finds_cycle() {
+        // 20, 8, 2, 3, ... 6, 0, 1, 2, ...:
+        let step = |&n: &u64| if n > 10 { 8 } else { (n + 1) % 7 };
+        for &method in &[Method::Hashing, Method::Brent] {
+            let mut seq = Sequence::with_method(20u64, step, method);
+            assert_eq!(seq.find_cycle(), Cycle { start: 2, period: 7, drift: 0 });
+            for n in 0..100 {
+                let expected = (0..n).fold(20, |n, _| step(&n));
+                assert_eq!(seq.state_at(n), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_drift() {
+        // Settles down after a few steps into a 2-cycle moving 3 along each time:
+        let step = |g: &Glider| match g.shape {
+            0..=2 => Glider { shape: g.shape + 1, offset: g.offset },
+            3 => Glider { shape: 4, offset: g.offset + 1 },
+            _ => Glider { shape: 3, offset: g.offset + 2 }
+        };
+        for &method in &[Method::Hashing, Method::Brent] {
+            let mut seq = Sequence::with_method(Glider { shape: 0, offset: 0 }, step, method);
+            assert_eq!(seq.find_cycle(), Cycle { start: 3, period: 2, drift: 3 });
+            assert_eq!(seq.state_at(1_000_000_002), Glider { shape: 4, offset: 1_499_999_998 });
+
+            let mut g = Glider { shape: 0, offset: 0 };
+            for n in 0..50 {
+                assert_eq!(seq.state_at(n), g);
+                g = step(&g);
+            }
+        }
+    }
+
+    #[test]
+    fn no_cycle_needed_nearby() {
+        // This never repeats, but we can still ask for states close by:
+        for &method in &[Method::Hashing, Method::Brent] {
+            let mut seq = Sequence::with_method(0u64, |&n: &u64| n + 1, method);
+            assert_eq!(seq.state_at(20), 20);
+            assert_eq!(seq.state_at(10), 10);
+        }
+    }
+}
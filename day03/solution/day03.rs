@@ -1,16 +1,16 @@
-use crate::{square::Square, canvas::Canvas};
+use grid::{Grid, Rect};
 use std::collections::HashSet;
 
 fn main() {
 
     let filename = std::env::args().nth(1).expect("need puzzle input");
-    let squares: Vec<(usize,Square)> = std::fs::read_to_string(filename)
+    let squares: Vec<(usize,Rect)> = std::fs::read_to_string(filename)
         .expect("can't open file")
         .lines()
         .map(square::parse)
         .collect();
 
-    // This is what we'll draw onto the Canvas, to keep
+    // This is what we'll draw onto the grid, to keep
     // track of the ID responsible for each pixel, or
     // whether the pixel is already overlapping:
     #[derive(Clone,Copy,PartialEq,Eq)]
@@ -22,11 +22,15 @@ fn main() {
     // Draw our pixels onto the canvas, merging each
     // with the current value drawn and keeping track
     // of overlaps for part 2:
-    let mut canvas = Canvas::new(1000,1000);
+    let mut canvas = Grid::new(Rect::new(0,0,1000,1000), None);
     let mut overlapping = HashSet::new();
     for &(value,s) in &squares {
-        canvas.draw(&s, |current_value| {
-            Some(match current_value {
+        for p in s.points() {
+            let pixel = match canvas.get_mut(p) {
+                Some(pixel) => pixel,
+                None => continue
+            };
+            *pixel = Some(match *pixel {
                 None => {
                     Pixel::Value(value)
                 },
@@ -39,14 +43,14 @@ fn main() {
                     overlapping.insert(value);
                     Pixel::Overlapping
                 }
-            })
-        })
+            });
+        }
     }
 
     // Count overlaps:
     let mut num_overlapping = 0;
     for v in canvas.values() {
-        if let Some(Pixel::Overlapping) = v {
+        if let Some(Pixel::Overlapping) = *v {
             num_overlapping += 1;
         }
     }
@@ -61,62 +65,19 @@ fn main() {
 
 }
 
-/// Defining and parsing Squares:
+/// Parsing squares into the rects they cover:
 mod square {
     use regex::Regex;
     use lazy_static::lazy_static;
+    use grid::Rect;
 
-    #[derive(Copy,Clone,Debug)]
-    pub struct Square {
-        pub left: usize,
-        pub top: usize,
-        pub width: usize,
-        pub height: usize
-    }
-
-    pub fn parse(s: &str) -> (usize, Square) {
+    pub fn parse(s: &str) -> (usize, Rect) {
         lazy_static! {
             static ref re: Regex = Regex::new(r"#(\d+) @ (\d+),(\d+): (\d+)x(\d+)").unwrap();
         }
         let caps = re.captures(s.trim()).unwrap();
-        let get = |n| caps.get(n).unwrap().as_str().parse().unwrap();
-        (get(1), Square { left: get(2), top: get(3), width: get(4), height: get(5) })
+        let get = |n| caps.get(n).unwrap().as_str().parse::<usize>().unwrap();
+        (get(1), Rect::new(get(2) as i64, get(3) as i64, get(4), get(5)))
     }
 
 }
-
-/// A Canvas to draw squares onto:
-mod canvas {
-    use crate::square;
-
-    pub struct Canvas<T> {
-        width: usize,
-        height: usize,
-        inner: Vec<Option<T>>,
-    }
-
-    impl <T: Clone> Canvas<T> {
-        pub fn new(width: usize, height: usize) -> Canvas<T> {
-            Canvas {
-                width: width,
-                height: height,
-                inner: vec![None; width * height]
-            }
-        }
-        pub fn draw<F>(&mut self, square: &square::Square, mut merge: F)
-        where F: FnMut(Option<T>) -> Option<T> {
-            let right = usize::min(self.width, square.left+square.width);
-            let bottom = usize::min(self.height, square.top+square.height);
-            for x in square.left .. right {
-                for y in square.top .. bottom {
-                    let idx = y * self.width + x;
-                    self.inner[idx] = merge(self.inner[idx].clone());
-                }
-            }
-        }
-        pub fn values(&self) -> impl Iterator<Item=Option<T>> + '_ {
-            self.inner.iter().cloned()
-        }
-    }
-
-}
\ No newline at end of file
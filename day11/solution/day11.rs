@@ -1,38 +1,145 @@
-use self::vec2d::Vec2D;
-
-fn main() {
-    let serial_no = 7857;
-    let mut cells = Vec2D::new(300,300,0);
-    for x in 1..=300 {
-        for y in 1..=300 {
-            cells.set(x-1, y-1, compute_cell(serial_no, x,y));
+use grid::{Grid, Rect, SummedArea};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::error::Error;
+use std::result;
+
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
+type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
+
+// Usage: day11 <input> [--size WIDTHxHEIGHT] [--top K]
+//
+// The input is either a serial number, in which case the power of each cell
+// is computed from it, or a grid of power values (one row per line). --size
+// is only for serial numbers (and is 300x300 if not given); a grid is as big
+// as it is.
+fn main() -> Result<()> {
+    let filename = std::env::args().nth(1).expect("need puzzle input");
+    let input = std::fs::read_to_string(filename)?;
+    let opts = parse_opts(std::env::args().skip(2))?;
+
+    let cells = match input.trim().parse::<usize>() {
+        Ok(serial_no) => {
+            let (width, height) = opts.size.unwrap_or((300, 300));
+            power_grid(width, height, |x,y| compute_cell(serial_no, x, y))
+        },
+        Err(_) if opts.size.is_some() => {
+            return Err(err!("--size can only be given when the input is a serial number"));
+        },
+        Err(_) => {
+            parse_grid(&input)?
         }
-    }
+    };
+    let sums = cells.summed_area();
+    let largest = cells.width().min(cells.height());
 
     // Star 1: Find best location for 3x3 grid:
-    let (x, y, _) = best_cell_of_size(&cells, 3);
-    println!("Star 1: {},{}", x, y);
-
-    // Star 2: Find best location for any grid of size 1..=300:
-    let (x, y, size, _) = (1..=300)
-        .map(|size| {
-            let (x, y, sum) = best_cell_of_size(&cells, size);
-            (x, y, size, sum)
+    match best_cells_of_size(&sums, 3, 1).first() {
+        Some((x, y, _)) => println!("Star 1: {},{}", x, y),
+        None => println!("Star 1: grid is smaller than 3x3")
+    }
+
+    // Star 2: Find best location for any grid of size 1..=largest
+    // (build with `--features parallel` to search sizes in parallel):
+    #[cfg(feature = "parallel")]
+    let sizes = (1..=largest).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let sizes = 1..=largest;
+    let (x, y, size, _) = sizes
+        .flat_map(|size| {
+            best_cells_of_size(&sums, size, 1)
+                .into_iter()
+                .map(move |(x, y, sum)| (x, y, size, sum))
+                .collect::<Vec<_>>()
         })
         .max_by_key(|(_,_,_,sum)| *sum)
-        .unwrap();
+        .ok_or_else(|| err!("Grid is empty"))?;
     println!("Star 2: {},{},{}", x, y, size);
+
+    // Optionally, report the top few squares of every size:
+    if let Some(k) = opts.top {
+        for size in 1..=largest {
+            let best: Vec<String> = best_cells_of_size(&sums, size, k)
+                .into_iter()
+                .map(|(x, y, sum)| format!("{},{} ({})", x, y, sum))
+                .collect();
+            println!("Size {}: {}", size, best.join(", "));
+        }
+    }
+
+    Ok(())
 }
 
-fn best_cell_of_size(cells: &Vec2D<i32>, size: usize) -> (usize,usize,i32) {
-    let sums =
-        (0 ..= cells.width() - size).flat_map(|x| {
-            (0 ..= cells.height() - size).map(move |y| {
-                let sum = cells.values_sub(x, y, size, size).sum();
-                (x+1, y+1, sum) // go from 0 to 1 indexed
-            })
-        });
-    sums.max_by_key(|(_x,_y,sum)| *sum).unwrap()
+struct Opts {
+    size: Option<(usize, usize)>,
+    top: Option<usize>
+}
+
+fn parse_opts(mut args: impl Iterator<Item=String>) -> Result<Opts> {
+    let mut opts = Opts { size: None, top: None };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| err!("Expected a value after '{}'", arg));
+        match &*arg {
+            "--size" => {
+                let v = value()?;
+                let mut dims = v.split('x').map(|n| n.trim().parse::<usize>());
+                match (dims.next(), dims.next(), dims.next()) {
+                    (Some(Ok(w)), Some(Ok(h)), None) => { opts.size = Some((w, h)) },
+                    _ => return Err(err!("Expected --size WIDTHxHEIGHT but got '{}'", v))
+                }
+            },
+            "--top" => {
+                opts.top = Some(value()?.parse()?);
+            },
+            _ => return Err(err!("Unexpected argument '{}'", arg))
+        }
+    }
+    Ok(opts)
+}
+
+// Build a 1-indexed grid of the size given, using some function to work
+// out the power of each cell from its coordinates:
+fn power_grid(width: usize, height: usize, power: impl Fn(usize, usize) -> i32) -> Grid<i32> {
+    let mut cells = Grid::new(Rect::new(1,1,width,height), 0);
+    for p in cells.bounds().points() {
+        cells.set(p, power(p.x as usize, p.y as usize));
+    }
+    cells
+}
+
+// Parse a 1-indexed grid of whitespace separated power values:
+fn parse_grid(s: &str) -> Result<Grid<i32>> {
+    let rows = s.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.split_whitespace().map(|n| n.parse::<i32>()).collect())
+        .collect::<result::Result<Vec<Vec<i32>>,_>>()?;
+    let width = rows.first().map(|r| r.len()).unwrap_or(0);
+    if let Some(y) = rows.iter().position(|r| r.len() != width) {
+        return Err(err!("Row {} has {} values but expected {}", y + 1, rows[y].len(), width));
+    }
+    Ok(power_grid(width, rows.len(), |x,y| rows[y-1][x-1]))
+}
+
+// The top `k` squares of some size (by sum), best first:
+fn best_cells_of_size(cells: &SummedArea, size: usize, k: usize) -> Vec<(i64,i64,i64)> {
+    let bounds = cells.bounds();
+    if size > bounds.width || size > bounds.height {
+        return Vec::new();
+    }
+    let corners = Rect::new(bounds.x, bounds.y, bounds.width - size + 1, bounds.height - size + 1);
+    let mut sums: Vec<_> = corners.points().map(|p| {
+        let sum = cells.sum(Rect::new(p.x, p.y, size, size));
+        (p.x, p.y, sum)
+    }).collect();
+    let order = |&(x,y,sum): &(i64,i64,i64)| (-sum, y, x);
+    if k == 0 {
+        return Vec::new();
+    } else if k < sums.len() {
+        sums.select_nth_unstable_by_key(k - 1, order);
+        sums.truncate(k);
+    }
+    sums.sort_by_key(order);
+    sums
 }
 
 fn compute_cell(serial_no: usize, x: usize, y: usize) -> i32 {
@@ -43,39 +150,66 @@ fn compute_cell(serial_no: usize, x: usize, y: usize) -> i32 {
     hundreds as i32 - 5
 }
 
-// A quick 2D vector which can return iterators over 2D sub-ranges:
-mod vec2d {
-    #[derive(Clone,Debug)]
-    pub struct Vec2D<T> {
-        width: usize,
-        height: usize,
-        values: Vec<T>
-    }
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    impl <T> Vec2D<T> {
-        pub fn new(width: usize, height: usize, value: T) -> Vec2D<T> where T: Clone {
-            Vec2D {
-                width: width,
-                height: height,
-                values: vec![value; width * height]
-            }
-        }
-        pub fn set(&mut self, x: usize, y: usize, value: T) {
-            let idx = y * self.width + x;
-            self.values[idx] = value;
-        }
-        pub fn values_sub(&self, x: usize, y: usize, width: usize, height: usize) -> impl Iterator<Item=&T> {
-            (y..y+height).flat_map(move |y| {
-                (x..x+width).map(move |x| {
-                    &self.values[y * self.width + x]
-                })
+    // The best square of any size, as (x, y, size, sum):
+    fn best_of_any_size(sums: &SummedArea) -> (i64, i64, usize, i64) {
+        let bounds = sums.bounds();
+        (1..=bounds.width.min(bounds.height))
+            .flat_map(|size| {
+                best_cells_of_size(sums, size, 1).into_iter().map(move |(x, y, sum)| (x, y, size, sum))
             })
+            .max_by_key(|(_,_,_,sum)| *sum)
+            .unwrap()
+    }
+
+    #[test]
+    fn cell_power() {
+        assert_eq!(compute_cell(8, 3, 5), 4);
+        assert_eq!(compute_cell(57, 122, 79), -5);
+        assert_eq!(compute_cell(39, 217, 196), 0);
+        assert_eq!(compute_cell(71, 101, 153), 4);
+    }
+
+    #[test]
+    fn serial_examples() {
+        for &(serial_no, star1, star2) in &[(18, (33, 45, 29), (90, 269, 16, 113)), (42, (21, 61, 30), (232, 251, 12, 119))] {
+            let cells = power_grid(300, 300, |x,y| compute_cell(serial_no, x, y));
+            let sums = cells.summed_area();
+            assert_eq!(best_cells_of_size(&sums, 3, 1), vec![star1]);
+            assert_eq!(best_of_any_size(&sums), star2);
         }
-        pub fn width(&self) -> usize {
-            self.width
-        }
-        pub fn height(&self) -> usize {
-            self.height
-        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn top_k_order() {
+        // Ties are broken by y and then x:
+        let sums = parse_grid("1 2 1\n2 0 2\n1 2 1").unwrap().summed_area();
+        assert_eq!(best_cells_of_size(&sums, 1, 3), vec![(2,1,2), (1,2,2), (3,2,2)]);
+        assert_eq!(best_cells_of_size(&sums, 2, 3), vec![(1,1,5), (2,1,5), (1,2,5)]);
+        assert_eq!(best_cells_of_size(&sums, 2, 10), vec![(1,1,5), (2,1,5), (1,2,5), (2,2,5)]);
+        assert_eq!(best_cells_of_size(&sums, 3, 2), vec![(1,1,12)]);
+        assert!(best_cells_of_size(&sums, 4, 1).is_empty());
+        assert!(best_cells_of_size(&sums, 1, 0).is_empty());
+    }
+
+    #[test]
+    fn bad_grid() {
+        assert!(parse_grid("1 2\n3").is_err());
+        assert!(parse_grid("1 2\n3 x").is_err());
+        assert!(parse_grid("1 2\n\n3 -4\n").is_ok());
+    }
+
+    #[test]
+    fn size_option() {
+        let opts = parse_opts(["--size", "10x20"].iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(opts.size, Some((10, 20)));
+        assert!(parse_opts(["--size", "10"].iter().map(|s| s.to_string())).is_err());
+        assert!(parse_opts(["--size"].iter().map(|s| s.to_string())).is_err());
+    }
+}
@@ -1,157 +1,245 @@
-use self::plants::Plants;
-use std::collections::HashMap;
+use self::plants::{Plants, Rules};
+use cycle::Sequence;
+use std::error::Error;
+use std::result;
 
-fn main() {
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
+type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
+
+fn main() -> Result<()> {
     let filename = std::env::args().nth(1).expect("need puzzle input");
-    let file = std::fs::read_to_string(filename)
-        .expect("can't open file");
+    let file = std::fs::read_to_string(filename)?;
 
-    let (initial, patterns) = extract_data_from_file(&file);
+    let (initial, rules) = extract_data_from_file(&file)?;
 
-    // Star 1: just do 20 steps and count plant numbers:
-    let mut plants = Plants::from_iter(initial.clone());
-    for _ in 0..20 {
-        plants.step(&patterns);
-    }
-    let sum: i64 = plants.positions().sum();
-    println!("Star 1: {}", sum);
-
-    // Star 2: Given some observation, I notice that after a certain
-    // step, the values start being identical after every step and just
-    // the offset is different, so find the values/offset and fast-forward!
-    let mut plants = Plants::from_iter(initial.clone());
-    let mut last_values = plants.values();
-    let mut last_offset = plants.offset();
-    let mut next_offset = plants.offset();
-    let mut last_step = 0;
-    for step in 1.. {
-        plants.step(&patterns);
-        let next_values = plants.values();
-        let next_step = step;
-        next_offset = plants.offset();
-        if next_values == last_values {
-            break;
+    // Each generation of plants follows from the last:
+    let mut generations = Sequence::new(Plants::from_iter(initial), |plants| {
+        plants.step(&rules)
+    });
+
+    // Star 1: just do 20 steps and count plant numbers.
+    // Star 2: After a certain step, the values start being identical after
+    // every step and just the offset is different, so find that cycle and
+    // fast-forward!
+    for &(star, n) in &[(1, 20), (2, 50_000_000_000)] {
+        // If the background is full of plants, the sum is infinite, and there
+        // may not be any cycle to find, so don't bother looking:
+        let sum = if rules.background_at(n) {
+            None
         } else {
-            last_values = next_values;
-            last_offset = next_offset;
-            last_step = next_step;
+            generations.state_at(n).sum()
+        };
+        match sum {
+            Some(sum) => println!("Star {}: {}", star, sum),
+            None => println!("Star {}: infinite (after {} generations, every pot far enough out contains a plant)", star, n)
         }
     }
-    let offset_diff = (50_000_000_000 - last_step - 1) * (next_offset - last_offset);
-    let sum: i64 = plants.positions().map(|p| p + offset_diff).sum();
-    println!("Star 2: {}", sum);
 
+    Ok(())
 }
 
 mod plants {
-    use std::collections::VecDeque;
+    use cycle::State;
+    use crate::Result;
+
+    // ### The rules, compiled into a lookup table ###
+    //
+    // Each window of pots is read as a binary number (leftmost pot first,
+    // with a plant being a 1), and the table says whether that window leads
+    // to a plant. Windows not covered by any rule lead to an empty pot.
+    pub struct Rules {
+        width: usize,
+        table: Vec<bool>
+    }
+    impl Rules {
+        pub fn from_patterns(patterns: &[(Vec<bool>, bool)]) -> Result<Rules> {
+            let width = patterns.first().map(|(p,_)| p.len()).unwrap_or(1);
+            if width.is_multiple_of(2) {
+                return Err(err!("Patterns should be an odd number of pots wide, not {}", width));
+            }
+            if width > 20 {
+                return Err(err!("Patterns wider than 20 pots are not supported"));
+            }
+            let mut table = vec![None; 1 << width];
+            for (pattern, res) in patterns {
+                if pattern.len() != width {
+                    return Err(err!("Every pattern should be {} pots wide", width));
+                }
+                let idx = pattern.iter().fold(0, |idx, &b| idx << 1 | b as usize);
+                match table[idx] {
+                    Some(prev) if prev != *res => {
+                        return Err(err!("Conflicting rules for pattern {}", to_string(pattern)));
+                    },
+                    _ => { table[idx] = Some(*res) }
+                }
+            }
+            Ok(Rules {
+                width,
+                table: table.into_iter().map(|r| r.unwrap_or(false)).collect()
+            })
+        }
+        // Pots start off empty in the background, but does that change after
+        // some number of generations? If so, it either fills with plants for
+        // good or flips between full and empty every generation:
+        pub fn background_at(&self, n: usize) -> bool {
+            let from_empty = self.table[0];
+            let from_full = self.table[self.mask()];
+            match n {
+                0 => false,
+                _ if !from_empty => false,
+                _ if from_full => true,
+                _ => n % 2 == 1
+            }
+        }
+        // How many pots either side of a pot affect it:
+        fn radius(&self) -> usize {
+            self.width / 2
+        }
+        fn mask(&self) -> usize {
+            (1 << self.width) - 1
+        }
+    }
 
+    fn to_string(pattern: &[bool]) -> String {
+        pattern.iter().map(|&b| if b { '#' } else { '.' }).collect()
+    }
+
+    // ### A row of pots, one bit each ###
+    //
+    // The row stretches forever in both directions, so we store only the
+    // stretch of pots that differs from the background (which is what every
+    // other pot contains). If the rules turn a window of empty pots into a
+    // plant, the background fills with plants, and may flip back and forth.
+    #[derive(Clone)]
     pub struct Plants {
         offset: i64,
-        values: VecDeque<bool>
+        len: usize,
+        bits: Vec<u64>,
+        background: bool
+    }
+
+    // The same pattern of plants in a different place is the same state:
+    impl State for Plants {
+        type Key = (bool, usize, Vec<u64>);
+        fn key(&self) -> (bool, usize, Vec<u64>) {
+            (self.background, self.len, self.bits.clone())
+        }
+        fn offset(&self) -> i64 {
+            self.offset
+        }
+        fn translate(&self, by: i64) -> Plants {
+            Plants { offset: self.offset + by, ..self.clone() }
+        }
     }
 
     impl Plants {
         pub fn from_iter(it: impl IntoIterator<Item=bool>) -> Plants {
-            Plants {
-                offset: 0,
-                values: it.into_iter().collect()
+            let mut plants = Plants::empty(0, false);
+            for b in it {
+                plants.push(b);
             }
+            plants.trim();
+            plants
         }
-        pub fn step(&mut self, pats: &[[bool;5]]) {
-            self.add_padding();
-            let old = &self.values;
-            let mut new = self.values.clone();
-            for idx in 2..new.len()-2 {
-                let curr = [old[idx-2],old[idx-1],old[idx],old[idx+1],old[idx+2]];
-                if pats.iter().any(|p| *p == curr) {
-                    new[idx] = true;
-                } else {
-                    new[idx] = false;
-                }
+        pub fn step(&self, rules: &Rules) -> Plants {
+            // Pots up to `radius` beyond either end can become plants. We slide
+            // a window of `width` pots along to see what each becomes; after
+            // shifting in pot `idx`, the window is centred on pot `idx - radius`:
+            let radius = rules.radius();
+            let mask = rules.mask();
+            let background_window = if self.background { mask } else { 0 };
+            let background = rules.table[background_window];
+            let mut next = Plants::empty(self.offset - radius as i64, background);
+            let mut window = background_window;
+            for idx in 0 .. self.len + 2 * radius {
+                window = (window << 1 | self.get(idx) as usize) & mask;
+                next.push(rules.table[window]);
             }
-            self.values = new;
-            self.remove_padding();
-        }
-        pub fn positions(&mut self) -> impl Iterator<Item=i64> + '_ {
-            self.values
-                .iter()
-                .zip(self.offset..)
-                .filter(|(b,_)| **b)
-                .map(|(_,i)| i)
+            next.trim();
+            next
         }
-        pub fn values(&self) -> Vec<bool> {
-            self.values.iter().cloned().collect()
+        // The sum of the positions of every plant, or None if there are
+        // infinitely many of them:
+        pub fn sum(&self) -> Option<i64> {
+            if self.background {
+                return None;
+            }
+            let sum = (0..self.len)
+                .filter(|&idx| self.get(idx))
+                .map(|idx| self.offset + idx as i64)
+                .sum();
+            Some(sum)
         }
-        pub fn offset(&self) -> i64 {
-            self.offset
+        fn empty(offset: i64, background: bool) -> Plants {
+            Plants { offset, len: 0, bits: Vec::new(), background }
         }
-        fn add_padding(&mut self) {
-            let first_n = self.values
-                .iter()
-                .position(|b| *b)
-                .unwrap_or(self.values.len()-1);
-            let last_n = self.values
-                .iter().rev()
-                .position(|b| *b)
-                .unwrap_or(0);
-
-            if first_n < 5 {
-                (0..5-first_n).for_each(|_| {
-                    self.values.push_front(false);
-                    self.offset -= 1;
-                })
-            }
-            if last_n < 5 {
-                (0..5-last_n).for_each(|_| {
-                    self.values.push_back(false);
-                })
+        fn get(&self, idx: usize) -> bool {
+            if idx >= self.len {
+                return self.background;
             }
+            self.bits[idx / 64] >> (idx % 64) & 1 == 1
         }
-        fn remove_padding(&mut self) {
-            while let Some(false) = self.values.front() {
-                self.values.pop_front();
-                self.offset += 1;
+        fn push(&mut self, b: bool) {
+            if self.len.is_multiple_of(64) {
+                self.bits.push(0);
             }
-            while let Some(false) = self.values.back() {
-                self.values.pop_back();
+            self.bits[self.len / 64] |= (b as u64) << (self.len % 64);
+            self.len += 1;
+        }
+        // Drop pots matching the background from either end, so that the same
+        // plants always look the same regardless of how they came about:
+        fn trim(&mut self) {
+            let differs = |idx: &usize| self.get(*idx) != self.background;
+            let first = (0..self.len).find(differs);
+            let first = match first {
+                Some(first) => first,
+                None => {
+                    *self = Plants::empty(0, self.background);
+                    return;
+                }
+            };
+            let last = (0..self.len).rev().find(differs).unwrap();
+            let mut trimmed = Plants::empty(self.offset + first as i64, self.background);
+            for idx in first..=last {
+                trimmed.push(self.get(idx));
             }
+            *self = trimmed;
         }
     }
 
 }
 
-fn extract_data_from_file(file: &str) -> (Vec<bool>, Vec<[bool;5]>) {
-    let mut lines = file.lines().filter(|l| !l.is_empty());
+fn extract_data_from_file(file: &str) -> Result<(Vec<bool>, Rules)> {
+    let mut lines = file.lines().filter(|l| !l.trim().is_empty());
 
     let initial = lines
         .next()
-        .expect("initial input")
+        .ok_or_else(|| err!("Expected initial state"))?
         .bytes()
         .filter_map(to_bool)
         .collect();
 
-    let yes_patterns = lines
-        .filter_map(to_yes_pattern)
-        .collect();
+    let patterns = lines
+        .map(to_pattern)
+        .collect::<Result<Vec<_>>>()?;
 
-    (initial, yes_patterns)
+    Ok((initial, Rules::from_patterns(&patterns)?))
 }
 
-fn to_yes_pattern(line: &str) -> Option<[bool;5]> {
-    let mut pieces = line.split(" => ");
-    let bools = pieces.next().unwrap().bytes().filter_map(to_bool);
-    let res = pieces.next().unwrap().bytes().filter_map(to_bool).next().unwrap();
-
-    if !res {
-        return None;
-    }
-
-    let mut pat = [false;5];
-    for (b, p) in bools.zip(pat.iter_mut()) {
-        *p = b
-    }
-    Some(pat)
+fn to_pattern(line: &str) -> Result<(Vec<bool>, bool)> {
+    let mut pieces = line.split("=>");
+    let (pat, res) = match (pieces.next(), pieces.next(), pieces.next()) {
+        (Some(pat), Some(res), None) => (pat, res),
+        _ => return Err(err!("Expected a rule like '..#.. => #' but got '{}'", line))
+    };
+    let pat: Vec<bool> = pat.trim().bytes().map(to_bool).collect::<Option<_>>()
+        .ok_or_else(|| err!("Invalid pattern in '{}'", line))?;
+    let res = match res.trim().as_bytes() {
+        &[b] => to_bool(b),
+        _ => None
+    };
+    let res = res.ok_or_else(|| err!("Invalid result in '{}'", line))?;
+    Ok((pat, res))
 }
 
 fn to_bool(byte: u8) -> Option<bool> {
@@ -160,4 +248,67 @@ fn to_bool(byte: u8) -> Option<bool> {
         b'.' => Some(false),
         _ => None
     }
-}
\ No newline at end of file
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "initial state: #..#.#..##......###...###
+        ...## => #
+        ..#.. => #
+        .#... => #
+        .#.#. => #
+        .#.## => #
+        .##.. => #
+        .#### => #
+        #.#.# => #
+        #.### => #
+        ##.#. => #
+        ##.## => #
+        ###.. => #
+        ###.# => #
+        ####. => #";
+
+    // The sum of plants after some generations, and whether the
+    // rules say that the background is full of plants by then:
+    fn sum_at(input: &str, n: usize) -> (Option<i64>, bool) {
+        let (initial, rules) = extract_data_from_file(input).unwrap();
+        let mut plants = Plants::from_iter(initial);
+        for _ in 0..n {
+            plants = plants.step(&rules);
+        }
+        (plants.sum(), rules.background_at(n))
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(sum_at(EXAMPLE, 20), (Some(325), false));
+    }
+
+    #[test]
+    fn background_flips() {
+        // Empty pots fill with plants and full ones empty again, so every
+        // other generation has infinitely many plants. Generation 20 was
+        // checked against a brute force simulation:
+        let input = format!("{}\n..... => #\n##### => .", EXAMPLE);
+        assert_eq!(sum_at(&input, 19), (None, true));
+        assert_eq!(sum_at(&input, 20), (Some(233), false));
+
+        // The same answer comes out of the sequence used to find cycles:
+        let (initial, rules) = extract_data_from_file(&input).unwrap();
+        let mut generations = Sequence::new(Plants::from_iter(initial), |p| p.step(&rules));
+        assert_eq!(generations.state_at(20).sum(), Some(233));
+    }
+
+    #[test]
+    fn background_fills() {
+        // Once the pots fill up with plants they stay that way:
+        let input = format!("{}\n..... => #\n##### => #", EXAMPLE);
+        assert_eq!(sum_at(&input, 20), (None, true));
+        assert_eq!(sum_at(&input, 21), (None, true));
+    }
+}
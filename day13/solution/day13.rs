@@ -1,13 +1,31 @@
 use self::carts::State;
+use std::error::Error;
+use std::result;
 
-fn main() {
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
+type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
+
+fn main() -> Result<()> {
     let filename = std::env::args().nth(1).expect("need puzzle input");
     let file = std::fs::read_to_string(filename).expect("can't open file");
 
+    // Make sure the track is sane before we set any carts moving on it:
+    State::from_str(&file)?.validate()?;
+
+    // Optionally, render the state of the track after some number of ticks:
+    if let Some(ticks) = std::env::args().nth(2) {
+        let ticks: usize = ticks.parse()?;
+        let mut map = State::from_str(&file)?;
+        for _ in 0..ticks {
+            map.step();
+        }
+        println!("After {} ticks:\n{}", ticks, map.render());
+    }
+
     // First, find the first crash location:
     {
-        let mut map = State::from_str(&file);
-        while map.crashes().len() == 0 {
+        let mut map = State::from_str(&file)?;
+        while map.crashes().is_empty() {
             map.step();
         }
         let first_crash = map.crashes()[0];
@@ -16,7 +34,7 @@ fn main() {
 
     // Next, find the last cart standing:
     {
-        let mut map = State::from_str(&file);
+        let mut map = State::from_str(&file)?;
         while map.carts().len() > 1 {
             map.step();
         }
@@ -24,39 +42,123 @@ fn main() {
         println!("Star 2: {},{}", last_loc.x, last_loc.y);
     }
 
+    Ok(())
 }
 
 // All of our logic to do with carts and such lives in this module:
 mod carts {
     use std::collections::HashMap;
+    use grid::{Point, Rect, SparseGrid};
+    use crate::Result;
     use self::Direction::*;
     use self::Road::*;
 
     // ### This is our current state: ###
     pub struct State {
-        map: HashMap<Coords,Road>,
+        map: SparseGrid<Road>,
         carts: Vec<Cart>,
-        crashes: Vec<Coords>
+        crashes: Vec<Point>
     }
     impl State {
-        pub fn from_str(s: &str) -> State {
-            let mut map = HashMap::new();
+        pub fn from_str(s: &str) -> Result<State> {
+            let mut map = SparseGrid::new();
             let mut carts = Vec::new();
             for (y, line) in s.lines().enumerate() {
                 for (x, byte) in line.bytes().enumerate() {
+                    let location = Point::new(x as i64, y as i64);
                     if let Some(road) = Road::from_byte(byte) {
-                        map.insert(Coords{x,y}, road);
+                        map.set(location, road);
+                    } else if byte != b' ' {
+                        return Err(err!("Unexpected '{}' at {},{}", byte as char, x, y));
                     }
                     if let Some(dir) = Direction::from_byte(byte) {
                         carts.push(Cart {
                             next_turn: Turn::Left,
-                            location: Coords{x,y},
+                            location,
                             direction: dir
                         });
                     }
                 }
             }
-            State { map, carts, crashes: Vec::new() }
+            Ok(State { map, carts, crashes: Vec::new() })
+        }
+        // Check that every cart is on the track and that every piece of track
+        // joins up with its neighbours, so that no cart can ever drive off it:
+        pub fn validate(&self) -> Result<()> {
+            let shapes = self.shapes()?;
+            for cart in &self.carts {
+                match shapes.get(&cart.location) {
+                    Some(exits) if exits.contains(&cart.direction) => {},
+                    _ => return Err(err!("Cart at {},{} is not on the track", cart.location.x, cart.location.y))
+                }
+            }
+            Ok(())
+        }
+        // Work out which way each piece of road leads. A curve could be either
+        // of two corners, so start with every shape a piece of road could have
+        // and keep ruling out shapes that lead into a neighbour which doesn't
+        // lead back, or that ignore a neighbour which can only lead into them.
+        // Every piece of road must be left with exactly one shape:
+        fn shapes(&self) -> Result<HashMap<Point, &'static [Direction]>> {
+            let mut shapes: HashMap<Point, Vec<&'static [Direction]>> = self.map.iter()
+                .map(|(loc, road)| (loc, road.shapes().to_vec()))
+                .collect();
+            let mut locs: Vec<Point> = shapes.keys().cloned().collect();
+            locs.sort();
+
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for &loc in &locs {
+                    let leads_back = |dir: Direction| {
+                        shapes.get(&(loc + dir.offset()))
+                            .map(|options| {
+                                let back = |exits: &&[Direction]| exits.contains(&dir.opposite());
+                                (options.iter().any(back), !options.is_empty() && options.iter().all(back))
+                            })
+                            .unwrap_or((false, false))
+                    };
+                    let fits = |exits: &[Direction]| Direction::ALL.iter().all(|&dir| {
+                        let (could, must) = leads_back(dir);
+                        if exits.contains(&dir) { could } else { !must }
+                    });
+                    let options: Vec<_> = shapes[&loc].iter().cloned().filter(|exits| fits(exits)).collect();
+                    if options.is_empty() {
+                        return Err(match self.map.get(loc) {
+                            Some(UpRight) | Some(UpLeft) => err!("Curve at {},{} does not connect", loc.x, loc.y),
+                            _ => err!("Dead end at {},{}", loc.x, loc.y)
+                        });
+                    }
+                    if options.len() != shapes[&loc].len() {
+                        shapes.insert(loc, options);
+                        changed = true;
+                    }
+                }
+            }
+
+            locs.into_iter().map(|loc| match shapes[&loc].as_slice() {
+                &[exits] => Ok((loc, exits)),
+                _ => Err(err!("Curve at {},{} could turn either way", loc.x, loc.y))
+            }).collect()
+        }
+        // Draw the track as it stands, with carts on it and crashes marked 'X'.
+        // We draw from 0,0 so that locations in the drawing match the input:
+        pub fn render(&self) -> String {
+            let bounds = self.map.bounds();
+            let rect = Rect::new(0, 0, bounds.right().max(0) as usize, bounds.bottom().max(0) as usize);
+            let grid = self.map.render_region(rect, |loc, road| {
+                let cart = self.carts.iter().find(|c| c.location == loc);
+                let byte = if self.crashes.contains(&loc) {
+                    b'X'
+                } else if let Some(cart) = cart {
+                    cart.direction.to_byte()
+                } else {
+                    road.map(|r| r.to_byte()).unwrap_or(b' ')
+                };
+                byte as char
+            });
+            let lines: Vec<&str> = grid.lines().map(|l| l.trim_end()).collect();
+            lines.join("\n")
         }
         pub fn step(&mut self) {
 
@@ -71,9 +173,9 @@ mod carts {
                 }
                 // move the cart and then increment location counter:
                 else {
-                    cart.location.step(cart.direction);
+                    cart.location = cart.location + cart.direction.offset();
                     *visited.entry(cart.location).or_insert(0) += 1;
-                    let road = *self.map.get(&cart.location).unwrap();
+                    let road = *self.map.get(cart.location).expect("cart left the track; was it validated?");
                     cart.react_to(road);
                 }
             }
@@ -86,7 +188,7 @@ mod carts {
             }
 
             // keep remaining carts and add to crashed list:
-            let carts = std::mem::replace(&mut self.carts, Vec::new());
+            let carts = std::mem::take(&mut self.carts);
             let mut next_carts = vec![];
             for cart in carts {
                 if *visited.get(&cart.location).unwrap_or(&0) > 1 {
@@ -99,7 +201,7 @@ mod carts {
             self.carts = next_carts;
 
         }
-        pub fn crashes(&self) -> &[Coords] {
+        pub fn crashes(&self) -> &[Point] {
             &self.crashes
         }
         pub fn carts(&self) -> &[Cart] {
@@ -107,33 +209,15 @@ mod carts {
         }
     }
 
-    // ### X,Y location (y is first as we sort by it first) ###
-    #[derive(Debug,PartialOrd,Ord,PartialEq,Eq,Clone,Copy,Hash)]
-    pub struct Coords {
-        pub y: usize,
-        pub x: usize
-    }
-    impl Coords {
-        // Attempts to move coords out of grid are not handled or expected.
-        fn step(&mut self, dir: Direction) {
-            match dir {
-                Up    => { self.y -= 1 },
-                Down  => { self.y += 1 },
-                Left  => { self.x -= 1 },
-                Right => { self.x += 1 }
-            }
-        }
-    }
-
     // ### A Single cart ###
     #[derive(PartialEq,Eq,Clone)]
     pub struct Cart {
-        location: Coords,
+        location: Point,
         direction: Direction,
         next_turn: Turn
     }
     impl Cart {
-        pub fn loc(&self) -> Coords {
+        pub fn loc(&self) -> Point {
             self.location
         }
         fn react_to(&mut self, road: Road) {
@@ -191,6 +275,7 @@ mod carts {
         Left
     }
     impl Direction {
+        const ALL: [Direction; 4] = [Up, Down, Right, Left];
         fn from_byte(b: u8) -> Option<Direction> {
             match b {
                 b'^' => Some(Up),
@@ -223,6 +308,31 @@ mod carts {
                 Turn::Straight => *self
             }
         }
+        // How far one step in this direction moves us:
+        fn offset(self) -> Point {
+            match self {
+                Up => Point::new(0,-1),
+                Down => Point::new(0,1),
+                Left => Point::new(-1,0),
+                Right => Point::new(1,0)
+            }
+        }
+        fn opposite(&self) -> Direction {
+            match self {
+                Up => Down,
+                Down => Up,
+                Left => Right,
+                Right => Left
+            }
+        }
+        fn to_byte(self) -> u8 {
+            match self {
+                Up => b'^',
+                Right => b'>',
+                Down => b'v',
+                Left => b'<'
+            }
+        }
     }
 
     // ### A piece of road ###
@@ -245,6 +355,83 @@ mod carts {
                 _ => None
             }
         }
+        fn to_byte(self) -> u8 {
+            match self {
+                Horizontal => b'-',
+                Vertical => b'|',
+                UpRight => b'/',
+                UpLeft => b'\\',
+                Intersection => b'+'
+            }
+        }
+        // The sets of directions this piece of road could lead out in. Curves
+        // are ambiguous on their own; '/' could be either corner of a loop:
+        fn shapes(self) -> &'static [&'static [Direction]] {
+            match self {
+                Horizontal => &[&[Left, Right]],
+                Vertical => &[&[Up, Down]],
+                UpRight => &[&[Right, Down], &[Up, Left]],
+                UpLeft => &[&[Left, Down], &[Up, Right]],
+                Intersection => &[&[Up, Down, Left, Right]]
+            }
+        }
+    }
+
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = r"/->-\        
+|   |  /----\
+| /-+--+-\  |
+| | |  | v  |
+\-+-/  \-+--/
+  \------/   ";
+
+    #[test]
+    fn valid_track() {
+        assert!(State::from_str(EXAMPLE).unwrap().validate().is_ok());
+        // The second example, with carts right next to each other:
+        let track = "/>-<\\  \n|   |  \n| /<+-\\\n| | | v\n\\>+</ |\n  |   ^\n  \\<->/";
+        assert!(State::from_str(track).unwrap().validate().is_ok());
     }
 
+    #[test]
+    fn invalid_track() {
+        // Unknown characters are rejected outright:
+        assert!(State::from_str("/-?\\").is_err());
+        // Track that runs off the edge of the map:
+        assert!(State::from_str("-->-").unwrap().validate().is_err());
+        // A curve that leads nowhere:
+        assert!(State::from_str("/->-\\\n|   |\n\\---/ /").unwrap().validate().is_err());
+        // A curve that some track runs into the side of:
+        assert!(State::from_str("/>-/-\\\n|  | |\n\\--\\-/").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn render() {
+        let mut state = State::from_str(EXAMPLE).unwrap();
+        assert_eq!(state.render(), EXAMPLE.lines().map(|l| l.trim_end()).collect::<Vec<_>>().join("\n"));
+        while state.crashes().is_empty() {
+            state.step();
+        }
+        assert_eq!(state.render(), r"/---\
+|   |  /----\
+| /-+--+-\  |
+| | |  X |  |
+\-+-/  \-+--/
+  \------/");
+    }
+
+    #[test]
+    fn render_keeps_leading_space() {
+        let track = "  /->-\\\n  |   |\n  \\---/";
+        let state = State::from_str(track).unwrap();
+        assert_eq!(state.render(), track);
+    }
 }
@@ -16,7 +16,7 @@ fn main() {
 // Star 1:
 fn run_part1(s: &str) -> (i32, i32) {
     let opts = Opts::new().elf_damage(3);
-    let mut state = State::from_str(&s, opts);
+    let mut state = State::from_str(s, opts);
     run(&mut state)
 }
 
@@ -25,7 +25,7 @@ fn run_part2(s: &str) -> (i32, i32, i32) {
     let mut damage = 4;
     loop {
         let opts = Opts::new().elf_damage(damage).stop_on_elf_death(true);
-        let mut state = State::from_str(&s, opts);
+        let mut state = State::from_str(s, opts);
         let (round, total_health) = run(&mut state);
         if !state.did_an_elf_die() {
             return (round, total_health, damage);
@@ -49,8 +49,9 @@ fn run(state: &mut State) -> (i32,i32) {
 // # This module contains the things we need to simulate the battle #
 // ##################################################################
 mod battle {
-    use std::collections::{ HashMap, HashSet };
+    use std::collections::HashSet;
     use std::fmt::{self,Display};
+    use grid::{ Grid, Point, SparseGrid };
     use self::UnitType::*;
 
     const STARTING_HEALTH: i32 = 200;
@@ -82,8 +83,8 @@ mod battle {
     // Our game state:
     //
     pub struct State {
-        units: HashMap<Coords, Unit>,
-        walls: HashSet<Coords>,
+        units: SparseGrid<Unit>,
+        walls: Grid<bool>,
         stop_on_elf_death: bool,
         starting_elves: i32,
         elves_remaining: i32,
@@ -91,17 +92,18 @@ mod battle {
     }
     impl State {
         pub fn from_str(s: &str, opts: Opts) -> State {
-            let mut units = HashMap::new();
-            let mut walls = HashSet::new();
+            let map: Vec<&str> = s.trim().lines().map(|l| l.trim()).collect();
+            let map = map.join("\n");
+            let walls = Grid::from_ascii(&map, false, |byte| Some(byte == b'#'));
+            let mut units = SparseGrid::new();
             let mut starting_elves = 0;
             let mut starting_goblins = 0;
-            for (y, line) in s.trim().lines().enumerate() {
-                for (x, byte) in line.trim().bytes().enumerate() {
-                    let p = Coords{ x:x as i32, y:y as i32 };
+            for (y, line) in map.lines().enumerate() {
+                for (x, byte) in line.bytes().enumerate() {
+                    let p = Point::new(x as i64, y as i64);
                     match byte {
-                        b'#' => { walls.insert(p); },
                         b'E' => {
-                            units.insert(p, Unit{
+                            units.set(p, Unit{
                                 ty:Elf,
                                 damage:opts.elf_damage,
                                 health:STARTING_HEALTH
@@ -109,7 +111,7 @@ mod battle {
                             starting_elves += 1;
                         },
                         b'G' => {
-                            units.insert(p, Unit{
+                            units.set(p, Unit{
                                 ty:Goblin,
                                 damage:3,
                                 health:STARTING_HEALTH
@@ -132,7 +134,7 @@ mod battle {
         pub fn round(&mut self) -> bool {
 
             // Sort units by reading order so that we know how to progress:
-            let mut unit_coords: Vec<Coords> = self.units.keys().cloned().collect();
+            let mut unit_coords: Vec<Point> = self.units.points().collect();
             unit_coords.sort();
 
             // Track dead units so we don't try to use those coords:
@@ -160,28 +162,28 @@ mod battle {
                 }
 
                 // Get unit (if it's not been killed!)
-                let unit = *self.units.get(&coords).expect("unit expected");
+                let unit = *self.units.get(coords).expect("unit expected");
 
                 // Who is this unit fighting?
                 let enemy_ty = if unit.ty == Elf { Goblin } else { Elf };
 
                 // Move the unit if there is a path to move along:
                 if let Some(new_coords) = self.step_to_nearest_unit(coords, enemy_ty) {
-                    self.units.remove(&coords);
-                    self.units.insert(new_coords, unit);
+                    self.units.remove(coords);
+                    self.units.set(new_coords, unit);
                     coords = new_coords;
                 }
 
                 // Attack if we're near enough to an enemy:
                 if let Some(enemy_coords) = self.adjacent_unit_to_attack(coords, enemy_ty) {
-                    let enemy = self.units.get_mut(&enemy_coords).unwrap();
+                    let enemy = self.units.get_mut(enemy_coords).unwrap();
                     enemy.health -= unit.damage;
                     if enemy.health <= 0 {
                         match enemy.ty {
                             Elf => { self.elves_remaining -= 1; },
                             Goblin => { self.goblins_remaining -= 1; }
                         }
-                        self.units.remove(&enemy_coords);
+                        self.units.remove(enemy_coords);
                         dead_units.insert(enemy_coords);
                         finished_early = self.is_finished();
                     }
@@ -201,28 +203,26 @@ mod battle {
         pub fn did_an_elf_die(&self) -> bool {
             self.elves_remaining < self.starting_elves
         }
-        fn adjacent_units(&self, coords: Coords, ty: UnitType) -> impl Iterator<Item=(Coords,Unit)> + '_ {
-            coords.adjacent()
-                .into_iter()
-                .filter_map(move |c| {
-                    let unit = *self.units.get(&c)?;
-                    if unit.ty != ty { return None };
-                    Some((c, unit))
-                })
+        fn adjacent_units(&self, coords: Point, ty: UnitType) -> impl Iterator<Item=(Point,Unit)> + '_ {
+            self.units
+                .neighbours4(coords)
+                .filter(move |(_,unit)| unit.ty == ty)
+                .map(|(c,&unit)| (c, unit))
         }
-        fn adjacent_unit_to_attack(&self, coords: Coords, ty: UnitType) -> Option<Coords> {
+        fn adjacent_unit_to_attack(&self, coords: Point, ty: UnitType) -> Option<Point> {
             self.adjacent_units(coords, ty)
                 // when attacking, find unit with lowest health first, reading order if tie:
                 .min_by_key(|(c,unit)| (unit.health, *c))
                 .map(|(c,_)| c)
         }
-        fn next_available_coords<'a>(&'a self, coords: Coords) -> impl Iterator<Item=Coords> + 'a {
-            coords.adjacent()
-                .into_iter()
-                .filter(move |c| !self.walls.contains(c))
-                .filter(move |c| !self.units.contains_key(c))
+        fn next_available_coords<'a>(&'a self, coords: Point) -> impl Iterator<Item=Point> + 'a {
+            self.walls
+                .neighbours4(coords)
+                .filter(|(_,&is_wall)| !is_wall)
+                .map(|(c,_)| c)
+                .filter(move |&c| !self.units.contains(c))
         }
-        fn find_nearest_coord(&self, start_coords: Coords, pick_end: impl Fn(&[Coords]) -> Option<Coords>) -> Option<Coords> {
+        fn find_nearest_coord(&self, start_coords: Point, pick_end: impl Fn(&[Point]) -> Option<Point>) -> Option<Point> {
             let mut visited = HashSet::new();
             visited.insert(start_coords);
             let mut current = vec![start_coords];
@@ -247,14 +247,14 @@ mod battle {
             }
             None
         }
-        fn step_to_nearest_unit(&self, start_coords: Coords, ty: UnitType) -> Option<Coords> {
+        fn step_to_nearest_unit(&self, start_coords: Point, ty: UnitType) -> Option<Point> {
 
             // Find the best coord next to the closest enemy:
             let next_to_enemy = self.find_nearest_coord(start_coords, |coords| {
                 coords.iter()
                     .filter(|&c| self.adjacent_units(*c,ty).next().is_some())
                     .min()
-                    .map(|&c| c)
+                    .copied()
             })?;
 
             // No need to move if the coord we want is our starting one:
@@ -265,9 +265,9 @@ mod battle {
             // Find the coord next to our input that's closest when moving from said enemy:
             self.find_nearest_coord(next_to_enemy, |coords| {
                 coords.iter()
-                    .filter(|&c| c.adjacent().iter().any(|&adj| adj == start_coords))
+                    .filter(|&c| c.neighbours4().contains(&start_coords))
                     .min()
-                    .map(|&c| c)
+                    .copied()
             })
 
         }
@@ -276,21 +276,14 @@ mod battle {
     // pretty print our state to help with debug:
     impl Display for State {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            let mut height = 0;
-            let mut width = 0;
-
-            for coord in &self.walls {
-                height = height.max(coord.y + 1);
-                width = width.max(coord.x + 1);
-            }
-
-            for y in 0..height {
+            let bounds = self.walls.bounds();
+            for y in bounds.y..bounds.bottom() {
                 let mut units = vec![];
-                for x in 0..width {
-                    let pos = Coords{x,y};
-                    if self.walls.contains(&pos) {
+                for x in bounds.x..bounds.right() {
+                    let pos = Point::new(x,y);
+                    if self.walls.get(pos) == Some(&true) {
                         write!(f, "#")?;
-                    } else if let Some(unit) = self.units.get(&pos) {
+                    } else if let Some(unit) = self.units.get(pos) {
                         if unit.ty == Elf {
                             write!(f, "E")?;
                         } else {
@@ -308,24 +301,12 @@ mod battle {
                         write!(f, " G({})", unit.health)?;
                     }
                 }
-                write!(f, "\n")?;
+                writeln!(f)?;
             }
             Ok(())
         }
     }
 
-    #[derive(Debug,Clone,Copy,Eq,PartialEq,Ord,PartialOrd,Hash)]
-    pub struct Coords {
-        y: i32, // y first for reading order
-        x: i32
-    }
-    impl Coords {
-        pub fn adjacent(&self) -> Vec<Coords> {
-            let Coords { x, y } = *self;
-            vec![Coords{x:x,y:y-1}, Coords{x:x-1,y:y}, Coords{x:x+1,y:y}, Coords{x:x,y:y+1}]
-        }
-    }
-
     #[derive(Copy,Clone,Debug)]
     pub struct Unit {
         pub ty: UnitType,
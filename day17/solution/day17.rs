@@ -1,236 +1,540 @@
-use std::collections::HashSet;
-use self::tracer::Tracer;
+use grid::{Point, SparseGrid};
+use self::tracer::{Tracer, Cell};
+use self::render::Animation;
 use regex::Regex;
 use lazy_static::lazy_static;
+use std::error::Error;
+use std::result;
 
-fn main() {
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
+type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
+
+// Usage: day17 <input> [--spring X,Y ...] [--ascii FILE] [--png FILE]
+//                      [--animate FILE.gif [--every N]]
+//
+// Springs can also be given in the input as lines like `spring x=500, y=0`.
+// If none are given either way, there is one spring at x=500, y=0. The scan
+// can be saved once the water has settled as ASCII or as a PNG, and the water
+// filling it can be saved as an animated GIF, adding a frame every N steps.
+fn main() -> Result<()> {
     let filename = std::env::args().nth(1).expect("need puzzle input");
-    let lines: Vec<Line> = std::fs::read_to_string(filename)
-        .expect("can't open file")
-        .lines()
-        .map(parse_line)
-        .collect();
+    let input = std::fs::read_to_string(filename)?;
+    let opts = parse_opts(std::env::args().skip(2))?;
+    let Scan { lines, mut springs } = parse_scan(&input)?;
+    springs.extend(opts.springs);
+    if springs.is_empty() {
+        springs.push(Point::new(500, 0));
+    }
 
-    // Trace out all of the squares filled with water:
+    // Trace out all of the squares filled with water, working out whether
+    // each is settled or still flowing as we go:
     let walls = note_walls(&lines);
-    let mut tracer = Tracer::new(walls, 500);
-    let bottom = tracer.bottom();
-    let top = tracer.top();
-    tracer.trace();
-    let count_in_bounds = tracer
-        .visited()
-        .iter()
-        .filter(|&&(_,y)| y >= top && y <= bottom)
-        .count();
-    println!("Star 1: {}", count_in_bounds);
-
-    // Next, work backwards from the bottom removing any water
-    // that is not contained (ie left/right/up from bottom water):
-    let contained = remove_flowing(tracer);
-    let count_contained_in_bounds = contained
-        .iter()
-        .filter(|&&(_,y)| y >= top && y <= bottom)
-        .count();
-    println!("Star 2: {}", count_contained_in_bounds);
+    let mut tracer = Tracer::new(walls, &springs)?;
+    let mut animation = match opts.animate {
+        Some(path) => Some(Animation::new(&path, &tracer, opts.every)?),
+        None => None
+    };
+    tracer.trace(|tracer| match &mut animation {
+        Some(animation) => animation.add_frame(tracer),
+        None => Ok(())
+    })?;
+    if let Some(animation) = animation {
+        animation.finish(&tracer)?;
+    }
+    if let Some(path) = opts.ascii {
+        std::fs::write(path, tracer.render())?;
+    }
+    if let Some(path) = opts.png {
+        render::write_png(&path, &tracer)?;
+    }
 
-}
+    // Star 1: all of the water within the bounds of the scan:
+    let settled = tracer.count(Cell::Settled);
+    let flowing = tracer.count(Cell::Flowing);
+    println!("Star 1: {}", settled + flowing);
 
-fn remove_flowing(tracer: Tracer) -> HashSet<(usize,usize)> {
+    // Star 2: only the water that's settled:
+    println!("Star 2: {}", settled);
 
-    // Define some utility functions to help us remove bits:
-    fn remove_from(visited: &mut HashSet<(usize,usize)>, x:usize, mut y:usize) {
-        while visited.contains(&(x,y)) {
-            visited.remove(&(x,y));
-            remove_row(visited, x, y);
-            if y > 0 {
-                y -= 1;
-            } else {
-                return;
-            }
+    // With more than one spring, note how far the water from each one gets:
+    if springs.len() > 1 {
+        for &spring in &springs {
+            let reach = tracer.reach(spring);
+            let settled = reach.iter().filter(|&&p| tracer.get(p) == Cell::Settled).count();
+            println!("Spring at {},{}: reaches {} squares ({} settled)", spring.x, spring.y, reach.len(), settled);
         }
     }
-    fn remove_row(visited: &mut HashSet<(usize,usize)>, x:usize, y:usize) {
-        remove_row_direction(visited, x, y, true);
-        remove_row_direction(visited, x, y, false);
+
+    Ok(())
+}
+
+struct Scan {
+    lines: Vec<Line>,
+    springs: Vec<Point>
+}
+
+fn parse_scan(s: &str) -> Result<Scan> {
+    lazy_static!{
+        static ref spring_re: Regex = Regex::new(r"^spring\s+x=(-?\d+),\s*y=(-?\d+)$").unwrap();
     }
-    fn remove_row_direction(visited: &mut HashSet<(usize,usize)>, x:usize, y:usize, go_right:bool) {
-        let inc = |n| if go_right { n + 1 } else { n - 1 };
-        let mut next_x = inc(x);
-        while visited.contains(&(next_x,y)) {
-            visited.remove(&(next_x, y));
-            if y > 0 && visited.contains(&(next_x, y-1)) {
-                remove_from(visited, next_x, y-1);
-            }
-            next_x = inc(next_x);
+    let mut scan = Scan { lines: Vec::new(), springs: Vec::new() };
+    for line in s.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        if let Some(caps) = spring_re.captures(line) {
+            scan.springs.push(Point::new(caps[1].parse()?, caps[2].parse()?));
+        } else {
+            scan.lines.push(parse_line(line).ok_or_else(|| err!("Can't parse line '{}'", line))?);
         }
     }
+    Ok(scan)
+}
 
-    // Now, find the starting points and remove:
-    let bottom = tracer.bottom();
-    let mut visited = tracer.into_visited();
-    let sinks: Vec<(usize,usize)> = visited.iter()
-        .filter(|(_,y)| *y == bottom)
-        .cloned()
-        .collect();
+struct Opts {
+    springs: Vec<Point>,
+    ascii: Option<String>,
+    png: Option<String>,
+    animate: Option<String>,
+    every: usize
+}
 
-    for (x,y) in sinks {
-        remove_from(&mut visited, x,y);
+fn parse_opts(mut args: impl Iterator<Item=String>) -> Result<Opts> {
+    let mut opts = Opts { springs: Vec::new(), ascii: None, png: None, animate: None, every: 25 };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| err!("Expected a value after '{}'", arg));
+        match &*arg {
+            "--spring" => {
+                let v = value()?;
+                let mut coords = v.split(',').map(|n| n.trim().parse::<i64>());
+                match (coords.next(), coords.next(), coords.next()) {
+                    (Some(Ok(x)), Some(Ok(y)), None) => opts.springs.push(Point::new(x, y)),
+                    _ => return Err(err!("Expected --spring X,Y but got '{}'", v))
+                }
+            },
+            "--ascii" => { opts.ascii = Some(value()?) },
+            "--png" => { opts.png = Some(value()?) },
+            "--animate" => { opts.animate = Some(value()?) },
+            "--every" => {
+                opts.every = value()?.parse()?;
+                if opts.every == 0 {
+                    return Err(err!("--every should be at least 1"));
+                }
+            },
+            _ => return Err(err!("Unexpected argument '{}'", arg))
+        }
     }
-    visited
+    Ok(opts)
 }
 
-fn note_walls(lines: &[Line]) -> HashSet<(usize,usize)> {
-    let mut walls = HashSet::new();
+fn note_walls(lines: &[Line]) -> SparseGrid<()> {
+    let mut walls = SparseGrid::new();
     for line in lines.iter().cloned() {
         for x in line.x {
             for y in line.y.clone() {
-                walls.insert((x,y));
+                walls.set(Point::new(x,y), ());
             }
         }
     }
     walls
 }
 
-fn parse_line(s: &str) -> Line {
+fn parse_line(s: &str) -> Option<Line> {
     lazy_static!{
         static ref line_re: Regex = Regex::new(r"(x|y)=(\d+),\s+(x|y)=(\d+)..(\d+)").unwrap();
     }
-    let caps = line_re.captures(s).unwrap();
-    let first_l = caps.get(1).unwrap().as_str();
-    let get_n = |n| caps.get(n).unwrap().as_str().parse().unwrap();
+    let caps = line_re.captures(s)?;
+    let first_l = caps.get(1)?.as_str();
+    let get_n = |n: usize| caps[n].parse::<i64>().ok();
 
-    let fst_r = get_n(2) ..= get_n(2);
-    let snd_r = get_n(4) ..= get_n(5);
+    let fst_r = get_n(2)? ..= get_n(2)?;
+    let snd_r = get_n(4)? ..= get_n(5)?;
 
     let (x,y) = if first_l == "x" {
         (fst_r,snd_r)
     } else {
         (snd_r,fst_r)
     };
-    Line { x, y }
+    Some(Line { x, y })
 }
 
 #[derive(Debug,Clone)]
 struct Line {
-    x: std::ops::RangeInclusive<usize>,
-    y: std::ops::RangeInclusive<usize>
+    x: std::ops::RangeInclusive<i64>,
+    y: std::ops::RangeInclusive<i64>
 }
 
 mod tracer {
     use std::collections::HashSet;
+    use grid::{Grid, Point, Rect, SparseGrid};
+    use crate::Result;
+
+    // ### What each square of the scan contains ###
+    #[derive(Debug,Clone,Copy,PartialEq,Eq)]
+    pub enum Cell {
+        Sand,
+        Clay,
+        Flowing,
+        Settled
+    }
 
     pub struct Tracer {
-        water_x: usize,
-        top: usize,
-        bottom: usize,
-        sources: HashSet<(usize,usize)>,
-        walls: HashSet<(usize,usize)>,
-        visited: HashSet<(usize,usize)>
+        springs: Vec<Point>,
+        walls: Rect,
+        top: i64,
+        bottom: i64,
+        cells: Grid<Cell>
     }
     impl Tracer {
-        pub fn new(walls: HashSet<(usize,usize)>, start_x: usize) -> Tracer {
-            let top = walls.iter().map(|(_,y)| *y).min().unwrap_or(0);
-            let bottom = walls.iter().map(|(_,y)| *y).max().unwrap_or(0);
-            Tracer {
+        pub fn new(walls: SparseGrid<()>, springs: &[Point]) -> Result<Tracer> {
+            let top = walls.bounds().y;
+            let bottom = walls.bounds().bottom() - 1;
+            // Water can flow one square beyond the walls (or springs) on either
+            // side, and starts from the springs, so make sure that we have room
+            // for it all:
+            let wall_bounds = walls.bounds();
+            let mut bounds = wall_bounds;
+            for &spring in springs {
+                if walls.contains(spring) {
+                    return Err(err!("Spring at {},{} is inside clay", spring.x, spring.y));
+                }
+                bounds = bounds.including(spring);
+            }
+            let top_y = bounds.y.min(top);
+            let area = Rect::new(bounds.x - 1, top_y, bounds.width + 2, (bottom - top_y + 1).max(0) as usize);
+            let mut cells = Grid::new(area, Cell::Sand);
+            for p in walls.points() {
+                cells.set(p, Cell::Clay);
+            }
+            Ok(Tracer {
+                springs: springs.to_vec(),
+                walls: wall_bounds,
                 top,
                 bottom,
-                water_x: start_x,
-                walls: walls,
-                sources: HashSet::new(),
-                visited: HashSet::new()
-            }
+                cells
+            })
         }
-        pub fn visited(&self) -> &HashSet<(usize,usize)> {
-            &self.visited
+        // How many squares between the top and bottom of the scan contain some cell:
+        pub fn count(&self, cell: Cell) -> usize {
+            self.cells
+                .iter()
+                .filter(|&(p,&c)| c == cell && p.y >= self.top && p.y <= self.bottom)
+                .count()
         }
-        pub fn into_visited(self) -> HashSet<(usize,usize)> {
-            self.visited
-        }
-        pub fn top(&self) -> usize {
-            self.top
+        // The part of the scan worth looking at; the walls (plus a square either
+        // side for water to spill down), and up as far as the springs above them:
+        pub fn crop(&self) -> Rect {
+            let walls = self.walls;
+            let mut crop = Rect::new(walls.x - 1, walls.y, walls.width + 2, walls.height);
+            for &spring in &self.springs {
+                if spring.x >= crop.x && spring.x < crop.right() && spring.y < self.bottom {
+                    crop = crop.including(spring);
+                }
+            }
+            crop
         }
-        pub fn bottom(&self) -> usize {
-            self.bottom
+        // What to draw at some square; a spring, or whatever cell is there:
+        pub fn glyph(&self, p: Point) -> char {
+            if self.springs.contains(&p) {
+                return '+';
+            }
+            match self.get(p) {
+                Cell::Sand => '.',
+                Cell::Clay => '#',
+                Cell::Flowing => '|',
+                Cell::Settled => '~'
+            }
         }
-        #[allow(dead_code)]
-        pub fn stringify_region(&self, xs: impl Iterator<Item=usize> + Clone, ys: impl Iterator<Item=usize>) -> String {
+        pub fn render(&self) -> String {
+            let crop = self.crop();
             let mut out = String::new();
-            for y in ys {
-                for x in xs.clone() {
-                    let c = if self.visited.contains(&(x,y)) {
-                        '~'
-                    } else if self.walls.contains(&(x,y)) {
-                        '#'
-                    } else {
-                        '.'
-                    };
-                    out.push(c);
+            for y in crop.y .. crop.bottom() {
+                for x in crop.x .. crop.right() {
+                    out.push(self.glyph(Point::new(x,y)));
                 }
                 out.push('\n');
             }
             out
         }
-        pub fn trace(&mut self) {
-            self.trace_source(self.water_x, 0);
+        // Trace the water from every spring. Rather than recursing, we keep a stack
+        // of squares that water needs to flow on from, and work through it until
+        // there's nothing left to do. Each item falls until it hits something,
+        // and then spreads sideways, filling up a container a row at a time if
+        // it's enclosed, or spilling over the edge(s) if not. `on_step` is handed
+        // the scan after each item, to watch it filling up.
+        pub fn trace(&mut self, mut on_step: impl FnMut(&Tracer) -> Result<()>) -> Result<()> {
+            // Water from different springs that meets just merges into the same
+            // flow (see `flow_from`), so it doesn't matter which we start with:
+            let mut todo = self.springs.clone();
+            while let Some(p) = todo.pop() {
+                self.flow_from(p, &mut todo);
+                on_step(self)?;
+            }
+            Ok(())
         }
-        fn trace_source(&mut self, x: usize, mut y: usize) {
-            if self.sources.contains(&(x,y)) {
+        fn flow_from(&mut self, mut p: Point, todo: &mut Vec<Point>) {
+            if self.get(p) == Cell::Settled {
                 return;
             }
-            self.sources.insert((x,y));
 
-            // trace down until we hit a wall:
-            while !self.walls.contains(&(x,y+1)) {
-                y += 1;
-                if y > self.bottom {
-                    // we have gone off the bottom; done!
+            // Fall until we land on something (or drop off the bottom):
+            loop {
+                let below = Point::new(p.x, p.y + 1);
+                match self.get(below) {
+                    _ if below.y > self.bottom => return,
+                    Cell::Sand => {
+                        self.cells.set(below, Cell::Flowing);
+                        p = below;
+                    },
+                    // We've joined some other flowing water, which will
+                    // already be flowing on (or will be re-examined if it settles):
+                    Cell::Flowing => return,
+                    Cell::Clay | Cell::Settled => break
+                }
+            }
+
+            // Spread out sideways, rising up a row each time we fill one:
+            loop {
+                let (left, left_open) = self.scan(p, -1);
+                let (right, right_open) = self.scan(p, 1);
+                let row = (left..=right).map(|x| Point::new(x, p.y));
+
+                if !left_open && !right_open {
+                    for c in row {
+                        self.cells.set(c, Cell::Settled);
+                        // Any other water that landed on this row was held up
+                        // by water that's now settled, so it needs to spread again:
+                        let above = Point::new(c.x, c.y - 1);
+                        if c.x != p.x && self.get(above) == Cell::Flowing {
+                            todo.push(above);
+                        }
+                    }
+                    p = Point::new(p.x, p.y - 1);
+                    if self.get(p) != Cell::Flowing {
+                        return;
+                    }
+                } else {
+                    for c in row {
+                        if self.get(c) != Cell::Settled {
+                            self.cells.set(c, Cell::Flowing);
+                        }
+                    }
+                    for &(end, open) in &[(left, left_open), (right, right_open)] {
+                        let end = Point::new(end, p.y);
+                        if open && self.get(Point::new(end.x, end.y + 1)) == Cell::Sand {
+                            todo.push(end);
+                        }
+                    }
                     return;
                 }
-                self.visited.insert((x,y));
             }
-            // We hit a wall; now trace sideways up from last
-            // (x,y) seen to fill in the container, until we
-            // overflow it (ie trace over a wall).
-            self.fill_container_from(x, y);
         }
-        fn fill_container_from(&mut self, x: usize, mut y: usize) {
+        // Look along a row in some direction until we hit clay (returning the
+        // last square before it), or find nothing holding the water up (returning
+        // that square, and noting that the water will flow down from there):
+        fn scan(&self, p: Point, dx: i64) -> (i64, bool) {
+            let mut x = p.x;
             loop {
-                let right_done = self.fill_direction_from(x, y, true);
-                let left_done = self.fill_direction_from(x, y, false);
-                if right_done || left_done {
-                    return;
+                let next = Point::new(x + dx, p.y);
+                if self.get(next) == Cell::Clay {
+                    return (x, false);
+                }
+                x = next.x;
+                match self.get(Point::new(x, p.y + 1)) {
+                    Cell::Clay | Cell::Settled => {},
+                    Cell::Sand | Cell::Flowing => return (x, true)
+                }
+            }
+        }
+        // Every square of water (between the top and bottom of the scan) that
+        // water from some spring passes through or settles in. Water falls,
+        // spreads sideways along anything holding it up, and landing in a pool
+        // of settled water reaches the whole pool and the water running over it:
+        pub fn reach(&self, spring: Point) -> Vec<Point> {
+            let is_water = |p: Point| {
+                let c = self.get(p);
+                c == Cell::Flowing || c == Cell::Settled
+            };
+            let mut seen = HashSet::new();
+            let mut todo = vec![Point::new(spring.x, spring.y + 1)];
+            while let Some(p) = todo.pop() {
+                if !is_water(p) || !seen.insert(p) {
+                    continue;
+                }
+                let below = Point::new(p.x, p.y + 1);
+                todo.push(below);
+                if matches!(self.get(below), Cell::Clay | Cell::Settled) {
+                    todo.push(Point::new(p.x - 1, p.y));
+                    todo.push(Point::new(p.x + 1, p.y));
+                }
+                if self.get(p) == Cell::Settled {
+                    todo.push(Point::new(p.x, p.y - 1));
                 }
-                y -= 1;
             }
+            let mut reach: Vec<Point> = seen.into_iter()
+                .filter(|p| p.y >= self.top && p.y <= self.bottom)
+                .collect();
+            reach.sort();
+            reach
+        }
+        pub fn get(&self, p: Point) -> Cell {
+            self.cells.get(p).cloned().unwrap_or(Cell::Sand)
         }
-        fn fill_direction_from(&mut self, x: usize, y: usize, go_right: bool) -> bool {
-            let mut next_x = x;
-            let mut is_last_source_below = false;
-            while !self.walls.contains(&(next_x,y)) {
+    }
+}
 
-                let is_source = self.sources.contains(&(next_x,y));
-                let is_wall_below = self.walls.contains(&(next_x,y+1));
-                let is_visited_below = self.visited.contains(&(next_x,y+1));
+// Save the scan as images, using the same colour for each glyph as we go:
+mod render {
+    use std::fs::File;
+    use std::io::BufWriter;
+    use grid::Point;
+    use crate::tracer::Tracer;
+    use crate::Result;
 
-                // If there's nothing below us and we just stepped over a source,
-                // stop immediately; another source would cover it.
-                if !is_visited_below && !is_wall_below && is_last_source_below {
-                    return true;
-                }
+    // Sand, clay, flowing water, settled water and springs, in that order:
+    const PALETTE: [u8; 15] = [
+        0xf2, 0xe3, 0xc0,
+        0x8b, 0x5a, 0x2b,
+        0x7f, 0xc8, 0xf8,
+        0x1f, 0x4e, 0xa8,
+        0xe0, 0x20, 0x20
+    ];
 
-                self.visited.insert((next_x, y));
+    // The scan, cropped, as one palette index per square:
+    fn pixels(tracer: &Tracer) -> Vec<u8> {
+        let crop = tracer.crop();
+        crop.points().map(|p: Point| match tracer.glyph(p) {
+            '#' => 1,
+            '|' => 2,
+            '~' => 3,
+            '+' => 4,
+            _ => 0
+        }).collect()
+    }
 
-                // if we are above nothing, we've filled the container
-                // and our current location should be a new source to trace down:
-                if !is_wall_below && !is_visited_below {
-                    self.trace_source(next_x, y);
-                    return true;
-                }
+    pub fn write_png(path: &str, tracer: &Tracer) -> Result<()> {
+        let crop = tracer.crop();
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, crop.width as u32, crop.height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(&PALETTE[..]);
+        encoder.write_header()?.write_image_data(&pixels(tracer))?;
+        Ok(())
+    }
 
-                next_x = if go_right { next_x + 1 } else { next_x - 1 };
-                is_last_source_below = is_source;
+    // ### An animated GIF of the water filling up the scan ###
+    pub struct Animation {
+        encoder: gif::Encoder<BufWriter<File>>,
+        width: u16,
+        height: u16,
+        every: usize,
+        steps: usize
+    }
+    impl Animation {
+        pub fn new(path: &str, tracer: &Tracer, every: usize) -> Result<Animation> {
+            let crop = tracer.crop();
+            if crop.width > u16::MAX as usize || crop.height > u16::MAX as usize {
+                return Err(err!("The scan is too large to animate ({}x{})", crop.width, crop.height));
             }
-            false
+            let (width, height) = (crop.width as u16, crop.height as u16);
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = gif::Encoder::new(file, width, height, &PALETTE)?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            let mut animation = Animation { encoder, width, height, every, steps: 0 };
+            animation.write_frame(tracer, 5)?;
+            Ok(animation)
+        }
+        // Called after every step of the trace; only some become frames:
+        pub fn add_frame(&mut self, tracer: &Tracer) -> Result<()> {
+            self.steps += 1;
+            if self.steps.is_multiple_of(self.every) {
+                self.write_frame(tracer, 5)?;
+            }
+            Ok(())
+        }
+        // Linger on the final state for a few seconds before looping:
+        pub fn finish(mut self, tracer: &Tracer) -> Result<()> {
+            self.write_frame(tracer, 300)
+        }
+        fn write_frame(&mut self, tracer: &Tracer, delay: u16) -> Result<()> {
+            let mut frame = gif::Frame::from_indexed_pixels(self.width, self.height, &pixels(tracer), None);
+            frame.delay = delay;
+            self.encoder.write_frame(&frame)?;
+            Ok(())
         }
     }
-}
\ No newline at end of file
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "
+        x=495, y=2..7
+        y=7, x=495..501
+        x=501, y=3..7
+        x=498, y=2..4
+        x=506, y=1..2
+        x=498, y=10..13
+        x=504, y=10..13
+        y=13, x=498..504
+    ";
+
+    fn trace(input: &str) -> (Tracer, Vec<Point>) {
+        let Scan { lines, mut springs } = parse_scan(input).unwrap();
+        if springs.is_empty() {
+            springs.push(Point::new(500, 0));
+        }
+        let mut tracer = Tracer::new(note_walls(&lines), &springs).unwrap();
+        tracer.trace(|_| Ok(())).unwrap();
+        (tracer, springs)
+    }
+
+    #[test]
+    fn example() {
+        let (tracer, _) = trace(EXAMPLE);
+        assert_eq!(tracer.count(Cell::Settled), 29);
+        assert_eq!(tracer.count(Cell::Flowing), 57 - 29);
+
+        // The final picture in the puzzle, without the notes beside it or the
+        // water falling below the scan:
+        let puzzle = include_str!("puzzle.txt");
+        let start = puzzle.find("Finally, while there").unwrap();
+        let expected: String = puzzle[start..]
+            .lines()
+            .skip(2)
+            .take_while(|l| !l.is_empty() && !l.contains("below maximum y value"))
+            .map(|l| format!("{}\n", l.split_whitespace().next().unwrap()))
+            .collect();
+        assert_eq!(tracer.render(), expected);
+    }
+
+    #[test]
+    fn springs_merge() {
+        // Two springs filling the same container end up in one pool, and
+        // overflow it together:
+        let (tracer, springs) = trace("
+            spring x=498, y=0
+            spring x=502, y=0
+            x=495, y=2..6
+            x=505, y=2..6
+            y=6, x=495..505
+        ");
+        assert_eq!(tracer.count(Cell::Settled), 9 * 4);
+        assert_eq!(tracer.count(Cell::Flowing), 2 * 5);
+        assert_eq!(tracer.render(), "\
+            ....+...+....\n\
+            |||||||||||||\n\
+            |#~~~~~~~~~#|\n\
+            |#~~~~~~~~~#|\n\
+            |#~~~~~~~~~#|\n\
+            |#~~~~~~~~~#|\n\
+            |###########|\n");
+
+        // Water from either spring reaches all of it:
+        let reach = tracer.reach(springs[0]);
+        assert_eq!(reach.len(), 9 * 4 + 2 * 5);
+        assert_eq!(reach, tracer.reach(springs[1]));
+    }
+}
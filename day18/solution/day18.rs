@@ -1,185 +1,404 @@
-use std::collections::HashMap;
+use std::error::Error;
+use std::result;
+use self::automaton::{Automaton, Rules};
+use cycle::{Method, Sequence};
 
-fn main() {
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
+type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
+
+// Usage: day18 <input> [rules-file] [--brent]
+//
+// Without a rules file, the lumber collection rules from the puzzle are used.
+// With --brent, repetition is found without remembering every past state.
+fn main() -> Result<()> {
     let filename = std::env::args().nth(1).expect("need puzzle input");
-    let input: String = std::fs::read_to_string(filename).expect("can't open file");
+    let input: String = std::fs::read_to_string(filename)?;
+    let mut rules = Rules::lumber();
+    let mut method = Method::Hashing;
+    for arg in std::env::args().skip(2) {
+        if arg == "--brent" {
+            method = Method::Brent;
+        } else {
+            rules = Rules::from_str(&std::fs::read_to_string(arg)?)?;
+        }
+    }
+
+    // Each generation of acres follows from the last:
+    let acres = Automaton::from_str(&input, rules)?;
+    let mut generations = Sequence::with_method(acres, |acres| {
+        let mut acres = acres.clone();
+        acres.step();
+        acres
+    }, method);
 
     // 10 generations:
-    let mut acres = parse_acres(&input);
-    for _ in 0..10 {
-        acres = step_acres(&acres);
-    }
-    println!("Star 1: {}", calculate_score(&acres));
-
-    // find repetition to work out state after 1billion:
-    let mut acres = parse_acres(&input);
-    let mut seen = HashMap::new();
-    let (fst,nxt) = (0..).filter_map(|idx| {
-        let v = acres_to_vec(&acres);
-        seen.get(&v).and_then(|&fst| {
-            Some((fst,idx))
-        }).or_else(|| {
-            seen.insert(v, idx);
-            acres = step_acres(&acres);
-            None
-        })
-    }).next().unwrap();
-
-    // Repetition found; do the final rounds we need and job done:
-    let rounds_to_do = (1_000_000_000 - nxt) % (nxt - fst);
-    for _ in 0..rounds_to_do {
-        acres = step_acres(&acres);
-    }
-    println!("Star 2: {}", calculate_score(&acres));
+    println!("Star 1: {}", calculate_score(&generations.state_at(10)));
 
+    // Find repetition to work out state after 1billion:
+    println!("Star 2: {}", calculate_score(&generations.state_at(1_000_000_000)));
+
+    Ok(())
 }
 
-fn calculate_score(acres: &HashMap<Coord,Item>) -> usize {
-    let (w,l) = acres.values().fold((0,0), |(w,l), item| {
-        match item {
-            Item::Lumberyard => (w,l+1),
-            Item::Trees => (w+1,l),
-            Item::Open => (w,l)
-        }
-    });
-    w * l
+// The puzzle's "resource value"; wooded acres multiplied by lumberyards:
+fn calculate_score(acres: &Automaton) -> usize {
+    acres.count(b'|') * acres.count(b'#')
 }
 
-fn parse_acres(s: &str) -> HashMap<Coord,Item> {
-    let mut h = HashMap::new();
-    for (y, line) in s.lines().enumerate() {
-        for (x, byte) in line.bytes().enumerate() {
-            let item = match byte {
-                b'.' => Item::Open,
-                b'|' => Item::Trees,
-                b'#' => Item::Lumberyard,
-                _ => panic!("Character not supported: '{}'", byte as char)
-            };
-            h.insert(Coord{x,y}, item);
+// A cellular automaton whose states and transitions are given by a set of rules:
+mod automaton {
+    use std::hash::{Hash, Hasher};
+    use grid::Grid;
+    use cycle::State;
+    use crate::Result;
+
+    // The rules from the puzzle. Open acres ('.') fill with trees ('|') if 3+
+    // neighbours are trees, trees become lumberyards ('#') if 3+ neighbours are
+    // lumberyards, and lumberyards remain only if next to both:
+    const LUMBER_RULES: &str = "
+        states . | #
+        . -> | if | >= 3
+        | -> # if # >= 3
+        # -> # if # >= 1 and | >= 1
+        # -> .
+    ";
+
+    // ### A set of states and the rules for moving between them ###
+    //
+    // Rules are written one per line; first a line naming every state glyph,
+    // then transitions of the form `FROM -> TO [if GLYPH OP COUNT [and ...]]`,
+    // where OP is one of `<`, `<=`, `=`, `!=`, `>=` or `>` and COUNT is the
+    // number of the 8 surrounding cells in state GLYPH. The first transition
+    // whose conditions all hold applies; if none do, a cell stays as it is.
+    // Blank lines and lines starting with `//` are ignored.
+    #[derive(Clone,Debug)]
+    pub struct Rules {
+        glyphs: Vec<u8>,
+        transitions: Vec<Transition>
+    }
+    impl Rules {
+        pub fn lumber() -> Rules {
+            Rules::from_str(LUMBER_RULES).expect("built-in rules are valid")
+        }
+        pub fn from_str(s: &str) -> Result<Rules> {
+            let mut lines = s.lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with("//"));
+
+            let states = lines.next().ok_or_else(|| err!("Rules are empty"))?;
+            let mut words = states.split_whitespace();
+            if words.next() != Some("states") {
+                return Err(err!("Rules should begin with a 'states' line, not '{}'", states));
+            }
+            let mut glyphs = Vec::new();
+            for word in words {
+                let glyph = parse_glyph(word)?;
+                if glyphs.contains(&glyph) {
+                    return Err(err!("State '{}' is declared twice", word));
+                }
+                glyphs.push(glyph);
+            }
+
+            let mut rules = Rules { glyphs, transitions: Vec::new() };
+            for line in lines {
+                let transition = rules.parse_transition(line)
+                    .map_err(|e| err!("Invalid rule '{}': {}", line, e))?;
+                rules.transitions.push(transition);
+            }
+            Ok(rules)
+        }
+        pub fn glyphs(&self) -> &[u8] {
+            &self.glyphs
+        }
+        // The state some glyph corresponds to:
+        pub fn state(&self, glyph: u8) -> Option<u8> {
+            self.glyphs.iter().position(|&g| g == glyph).map(|s| s as u8)
+        }
+        // Given a state and a count of surrounding cells in each state, what's next?
+        pub fn next_state(&self, state: u8, counts: &[usize]) -> u8 {
+            self.transitions.iter()
+                .find(|t| t.from == state && t.conditions.iter().all(|c| c.holds(counts)))
+                .map(|t| t.to)
+                .unwrap_or(state)
+        }
+        fn parse_transition(&self, line: &str) -> Result<Transition> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() < 3 || words[1] != "->" {
+                return Err(err!("expected 'FROM -> TO'"));
+            }
+            let from = self.parse_state(words[0])?;
+            let to = self.parse_state(words[2])?;
+            let mut conditions = Vec::new();
+            let mut rest = &words[3..];
+            let mut joiner = "if";
+            while !rest.is_empty() {
+                if rest.len() < 4 || rest[0] != joiner {
+                    return Err(err!("expected '{} GLYPH OP COUNT'", joiner));
+                }
+                conditions.push(Condition {
+                    state: self.parse_state(rest[1])?,
+                    op: Op::from_str(rest[2])?,
+                    count: rest[3].parse()?
+                });
+                rest = &rest[4..];
+                joiner = "and";
+            }
+            Ok(Transition { from, to, conditions })
+        }
+        fn parse_state(&self, word: &str) -> Result<u8> {
+            let glyph = parse_glyph(word)?;
+            self.state(glyph).ok_or_else(|| err!("'{}' is not a declared state", word))
         }
     }
-    h
-}
 
-fn print_acres(acres: &HashMap<Coord,Item>) {
-    use std::io::Write;
-    let stdout = std::io::stdout();
-    let mut handle = stdout.lock();
-    for y in 0..50 {
-        for x in 0..50 {
-            write!(&mut handle, "{}", match acres.get(&Coord{x,y}) {
-                Some(Item::Open) => ".",
-                Some(Item::Lumberyard) => "#",
-                Some(Item::Trees) => "|",
-                None => "X"
-            }).unwrap();
-        }
-        write!(&mut handle, "\n");
+    fn parse_glyph(word: &str) -> Result<u8> {
+        match word.as_bytes() {
+            &[glyph] => Ok(glyph),
+            _ => Err(err!("'{}' should be a single character", word))
+        }
+    }
+
+    #[derive(Clone,Debug)]
+    struct Transition {
+        from: u8,
+        to: u8,
+        conditions: Vec<Condition>
     }
-}
 
-fn step_acres(acres: &HashMap<Coord,Item>) -> HashMap<Coord,Item> {
-    let mut h = HashMap::new();
-    for x in 0..50 {
-        for y in 0..50 {
-            let c = Coord{x,y};
-            h.insert(c, step_item(c, acres));
+    #[derive(Clone,Copy,Debug)]
+    struct Condition {
+        state: u8,
+        op: Op,
+        count: usize
+    }
+    impl Condition {
+        fn holds(&self, counts: &[usize]) -> bool {
+            let n = counts[self.state as usize];
+            match self.op {
+                Op::Lt => n < self.count,
+                Op::Le => n <= self.count,
+                Op::Eq => n == self.count,
+                Op::Ne => n != self.count,
+                Op::Ge => n >= self.count,
+                Op::Gt => n > self.count
+            }
         }
     }
-    h
-}
 
-fn step_item(c: Coord, acres: &HashMap<Coord,Item>) -> Item {
-    let item = acres.get(&c).expect("item expected");
-    let surrounds = count_surrounding(&c, acres);
-    match item {
-        Item::Open => {
-            if surrounds.trees >= 3 {
-                Item::Trees
-            } else {
-                Item::Open
+    #[derive(Clone,Copy,Debug)]
+    enum Op { Lt, Le, Eq, Ne, Ge, Gt }
+    impl Op {
+        fn from_str(s: &str) -> Result<Op> {
+            Ok(match s {
+                "<" => Op::Lt,
+                "<=" => Op::Le,
+                "=" | "==" => Op::Eq,
+                "!=" => Op::Ne,
+                ">=" => Op::Ge,
+                ">" => Op::Gt,
+                _ => return Err(err!("'{}' is not a valid comparison", s))
+            })
+        }
+    }
+
+    // ### A grid of cells, each in one of the states given by some rules ###
+    #[derive(Clone)]
+    pub struct Automaton {
+        rules: Rules,
+        cells: Grid<u8>
+    }
+    impl Automaton {
+        // Parse a grid of state glyphs; its size is whatever the input is:
+        pub fn from_str(s: &str, rules: Rules) -> Result<Automaton> {
+            let s = s.trim();
+            let mut bad_glyph = None;
+            let cells = Grid::from_ascii(s, 0, |glyph| {
+                let state = rules.state(glyph);
+                if state.is_none() && bad_glyph.is_none() {
+                    bad_glyph = Some(glyph);
+                }
+                state
+            });
+            if let Some(glyph) = bad_glyph {
+                return Err(err!("Character not supported: '{}'", glyph as char));
             }
-        },
-        Item::Trees => {
-            if surrounds.lumbaryard >= 3 {
-                Item::Lumberyard
-            } else {
-                Item::Trees
+            if s.lines().any(|l| l.len() != cells.width()) {
+                return Err(err!("Every line of the input should be the same length"));
             }
-        },
-        Item::Lumberyard => {
-            if surrounds.lumbaryard >= 1 && surrounds.trees >= 1 {
-                Item::Lumberyard
-            } else {
-                Item::Open
+            Ok(Automaton { rules, cells })
+        }
+        pub fn step(&mut self) {
+            let mut counts = vec![0; self.rules.glyphs().len()];
+            let cells = &self.cells;
+            let rules = &self.rules;
+            self.cells = cells.map(|p, &state| {
+                counts.iter_mut().for_each(|c| *c = 0);
+                for (_, &n) in cells.neighbours8(p) {
+                    counts[n as usize] += 1;
+                }
+                rules.next_state(state, &counts)
+            }, 0);
+        }
+        // How many cells are currently in the state with some glyph?
+        pub fn count(&self, glyph: u8) -> usize {
+            match self.rules.state(glyph) {
+                Some(state) => self.cells.values().filter(|&&s| s == state).count(),
+                None => 0
             }
         }
+        #[cfg(test)]
+        pub fn render(&self) -> String {
+            self.cells.render(|_, &state| self.rules.glyphs()[state as usize] as char)
+        }
     }
-}
-
-fn count_surrounding(c: &Coord, acres: &HashMap<Coord,Item>) -> Surrounding {
-    let mut open = 0;
-    let mut trees = 0;
-    let mut lumbaryard = 0;
-    for s in c.surrounding() {
-        match acres.get(&s) {
-            Some(Item::Open) => { open += 1 },
-            Some(Item::Trees) => { trees += 1 },
-            Some(Item::Lumberyard) => { lumbaryard += 1 },
-            None => { /* ignore */ }
+    impl State for Automaton {
+        type Key = Fingerprint;
+        fn key(&self) -> Fingerprint {
+            Fingerprint::new(&self.cells, self.rules.glyphs().len())
         }
     }
-    Surrounding { open, trees, lumbaryard }
-}
 
-fn acres_to_vec(acres: &HashMap<Coord,Item>) -> Vec<Item> {
-    let mut n = Vec::with_capacity(50 * 50);
-    for x in 0..50 {
-        for y in 0..50 {
-            n.push(*acres.get(&Coord{x,y}).unwrap());
+    // ### A compact copy of some cells, for cheaply spotting repeated states ###
+    //
+    // Each cell is packed into as few bits as its number of states needs (2
+    // for the lumber rules), and a hash is rolled over the packed words as we
+    // go, so that hashing a fingerprint is just handing over that number.
+    #[derive(Clone,Debug,Eq)]
+    pub struct Fingerprint {
+        hash: u64,
+        words: Vec<u64>
+    }
+    impl Fingerprint {
+        fn new(cells: &Grid<u8>, num_states: usize) -> Fingerprint {
+            let bits_per_cell = (usize::BITS - num_states.saturating_sub(1).leading_zeros()).max(1) as usize;
+            let cells_per_word = 64 / bits_per_cell;
+            let mut words = Vec::with_capacity(cells.width() * cells.height() / cells_per_word + 1);
+            let mut hash: u64 = 0;
+            let mut add_word = |word: u64| {
+                hash = hash.wrapping_mul(0x100000001b3).wrapping_add(word);
+                words.push(word);
+            };
+            let mut word = 0;
+            for (n, &state) in cells.values().enumerate() {
+                word = (word << bits_per_cell) | state as u64;
+                if n % cells_per_word == cells_per_word - 1 {
+                    add_word(word);
+                    word = 0;
+                }
+            }
+            add_word(word);
+            Fingerprint { hash, words }
+        }
+    }
+    impl PartialEq for Fingerprint {
+        fn eq(&self, other: &Fingerprint) -> bool {
+            self.hash == other.hash && self.words == other.words
+        }
+    }
+    impl Hash for Fingerprint {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            state.write_u64(self.hash)
         }
     }
-    n
-}
 
-#[derive(Eq,PartialEq,Copy,Clone,Debug)]
-struct Surrounding {
-    open: usize,
-    trees: usize,
-    lumbaryard: usize
 }
 
-#[derive(Hash,Eq,PartialEq,Copy,Clone,Debug)]
-enum Item {
-    Open,
-    Trees,
-    Lumberyard
-}
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
 
-#[derive(Hash,Eq,PartialEq,Copy,Clone,Debug)]
-struct Coord {
-    x: usize,
-    y: usize
-}
+    const EXAMPLE: &str = "
+        .#.#...|#.
+        .....#|##|
+        .|..|...#.
+        ..|#.....#
+        #.#|||#|#|
+        ...#.||...
+        .|....|...
+        ||...#|.#|
+        |.||||..|.
+        ...#.|..|.";
 
-impl Coord {
-    pub fn surrounding(&self) -> Vec<Coord> {
-        let Coord{x,y} = *self;
-        let mut cs = vec![];
-        cs.push(Coord{x:x+1,y:y});
-        cs.push(Coord{x:x+1,y:y+1});
-        cs.push(Coord{x:x,y:y+1});
-        if x > 0 {
-            cs.push(Coord{x:x-1,y:y});
-            cs.push(Coord{x:x-1,y:y+1});
-        }
-        if y > 0 {
-            cs.push(Coord{x:x,y:y-1});
-            cs.push(Coord{x:x+1,y:y-1});
-        }
-        if x > 0 && y > 0 {
-            cs.push(Coord{x:x-1,y:y-1});
+    // Lines are indented in the tests, so strip that off first:
+    fn grid(s: &str) -> String {
+        s.trim().lines().map(|l| l.trim()).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn lumber_example() {
+        let mut acres = Automaton::from_str(&grid(EXAMPLE), Rules::lumber()).unwrap();
+        for _ in 0..10 {
+            acres.step();
         }
-        cs
+        assert_eq!(acres.render().trim_end(), grid("
+            .||##.....
+            ||###.....
+            ||##......
+            |##.....##
+            |##.....##
+            |##....##|
+            ||##.####|
+            ||#####|||
+            ||||#|||||
+            ||||||||||"));
+        assert_eq!(calculate_score(&acres), 1147);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn custom_rules() {
+        // Conway's game of life, where a blinker flips between a row and a
+        // column of three cells:
+        let rules = Rules::from_str("
+            // Game of life:
+            states . #
+            . -> # if # = 3
+            # -> . if # < 2
+            # -> . if # > 3
+        ").unwrap();
+        let row = grid("
+            .....
+            .....
+            .###.
+            .....
+            .....");
+        let column = grid("
+            .....
+            ..#..
+            ..#..
+            ..#..
+            .....");
+        let mut cells = Automaton::from_str(&row, rules).unwrap();
+        cells.step();
+        assert_eq!(cells.render().trim_end(), column);
+        assert_eq!(cells.count(b'#'), 3);
+        cells.step();
+        assert_eq!(cells.render().trim_end(), row);
+    }
+
+    #[test]
+    fn bad_rules() {
+        // A glyph that isn't declared:
+        assert!(Rules::from_str("states . #\n. -> |").is_err());
+        // An operator that doesn't exist:
+        assert!(Rules::from_str("states . #\n. -> # if # ~ 3").is_err());
+        // A condition with something missing:
+        assert!(Rules::from_str("states . #\n. -> # if # >=").is_err());
+        // No states line:
+        assert!(Rules::from_str(". -> #").is_err());
+        assert!(Rules::from_str("").is_err());
+        // A state declared twice:
+        assert!(Rules::from_str("states . # .").is_err());
+    }
+
+    #[test]
+    fn bad_grid() {
+        // Ragged lines:
+        assert!(Automaton::from_str(".|#\n.|\n#..", Rules::lumber()).is_err());
+        // A glyph the rules don't know about:
+        assert!(Automaton::from_str(".|#\n.x#", Rules::lumber()).is_err());
+        assert!(Automaton::from_str(".|#\n.|#", Rules::lumber()).is_ok());
+    }
+}
@@ -1,179 +1,524 @@
 use crate::cave::Cave;
-use crate::solver::Solver;
+use crate::rules::Rules;
+use crate::solver::{Solver, Step};
+use std::error::Error;
+use std::io::IsTerminal;
+use std::result;
 
-fn main() {
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
+type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
 
-    let depth = 11739;
-    let target = (11,718);
+// Usage: day22 <input> [--rules FILE] [--route] [--ascii FILE] [--png FILE]
+//
+// The input gives the depth and target, as in the puzzle. Without a rules
+// file, the tool rules from the puzzle are used. With --route, every move
+// and tool switch along the fastest route is printed. The cave can be drawn
+// with the route on top as ASCII or as a PNG. Use `--ascii -` to print the
+// ASCII instead, which is coloured if printing to a terminal.
+fn main() -> Result<()> {
+
+    let filename = std::env::args().nth(1).expect("need puzzle input");
+    let (depth, target) = parse_input(&std::fs::read_to_string(filename)?)?;
+    let Opts { rules, show_route, ascii, png } = parse_opts(std::env::args().skip(2))?;
 
     let cave = Cave::new(depth, target);
 
     // Work out the risk level for star 1:
-    let mut risk_level = 0;
-    for y in 0..=target.1 {
-        for x in 0..=target.0 {
-            risk_level += cave.get((x,y)) as usize;
-        }
-    }
-    println!("Star 1: {}", risk_level);
+    println!("Star 1: {}", cave.risk_level());
 
     // Work out the fastest way to get from 0,0
     // to the target:
-    let mut solver = Solver::new(&cave);
-    println!("Star 2: {}", solver.solve());
+    let solver = Solver::with_rules(&cave, rules);
+    let route = solver.solve().ok_or_else(|| err!("The target can't be reached with these rules"))?;
+    println!("Star 2: {}", route.time);
+
+    if show_route {
+        for step in &route.steps {
+            match *step {
+                Step::Move { time, to } => println!("{:>5}: move to {},{}", time, to.0, to.1),
+                Step::Switch { time, to } => println!("{:>5}: switch to {}", time, solver.rules().tool_name(to))
+            }
+        }
+    }
+    if let Some(path) = ascii {
+        if path == "-" {
+            let colour = std::io::stdout().is_terminal();
+            print!("{}", render::ascii(&cave, &route, solver.rules(), colour));
+        } else {
+            std::fs::write(path, render::ascii(&cave, &route, solver.rules(), false))?;
+        }
+    }
+    if let Some(path) = png {
+        render::write_png(&path, &cave, &route, solver.rules().start())?;
+    }
+
+    Ok(())
+}
+
+struct Opts {
+    rules: Rules,
+    show_route: bool,
+    ascii: Option<String>,
+    png: Option<String>
+}
+
+fn parse_opts(mut args: impl Iterator<Item=String>) -> Result<Opts> {
+    let mut opts = Opts { rules: Rules::default(), show_route: false, ascii: None, png: None };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| err!("Expected a value after '{}'", arg));
+        match &*arg {
+            "--route" => { opts.show_route = true },
+            "--rules" => {
+                opts.rules = Rules::from_str(&std::fs::read_to_string(value()?)?)?;
+            },
+            "--ascii" => { opts.ascii = Some(value()?) },
+            "--png" => { opts.png = Some(value()?) },
+            _ => return Err(err!("Unexpected argument '{}'", arg))
+        }
+    }
+    Ok(opts)
+}
+
+// Parse the depth and target from input like "depth: 510\ntarget: 10,10":
+fn parse_input(s: &str) -> Result<(usize, (usize,usize))> {
+    let mut depth = None;
+    let mut target = None;
+    for line in s.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        if let Some(d) = line.strip_prefix("depth:") {
+            depth = Some(d.trim().parse()?);
+        } else if let Some(t) = line.strip_prefix("target:") {
+            let mut coords = t.split(',').map(|n| n.trim().parse::<usize>());
+            match (coords.next(), coords.next(), coords.next()) {
+                (Some(Ok(x)), Some(Ok(y)), None) => target = Some((x, y)),
+                _ => return Err(err!("Expected a target like 'target: 10,10' but got '{}'", line))
+            }
+        } else {
+            return Err(err!("Unexpected line '{}'", line));
+        }
+    }
+    match (depth, target) {
+        (Some(depth), Some(target)) => Ok((depth, target)),
+        (None, _) => Err(err!("The input doesn't give a depth")),
+        (_, None) => Err(err!("The input doesn't give a target"))
+    }
+}
+
+// The rules saying which tools can be used where, and how long things take:
+mod rules {
+    use crate::cave::Type;
+    use crate::Result;
+
+    // The rules from the puzzle. Rocky regions need the torch or climbing gear,
+    // wet ones need climbing gear or neither, and narrow ones need the torch or
+    // neither. Moving takes 1 minute and switching tools takes 7. We start with
+    // the torch and need to be holding it when we find the target:
+    const PUZZLE_RULES: &str = "
+        tools neither torch climbing-gear
+        start torch
+        finish torch
+        allow rocky torch climbing-gear
+        allow wet climbing-gear neither
+        allow narrow torch neither
+        move 1
+        switch 7
+    ";
+
+    // A tool is an index into the tools named in some rules:
+    pub type Tool = usize;
+
+    // ### A set of tools and the rules for using them ###
+    //
+    // Rules are written one per line; first a line naming every tool, and then
+    // any of the following, where TOOL is one of those names and REGION is one
+    // of `rocky`, `wet` or `narrow`:
+    //
+    //   start TOOL                  the tool held to begin with
+    //   finish TOOL                 the tool to be holding at the target (optional)
+    //   allow REGION TOOL...        which tools can be used in some region
+    //   move [TOOL] COST            the time to move holding a tool (or any tool)
+    //   switch [TOOL TOOL] COST     the time to switch between tools (or any tools)
+    //
+    // Later lines override earlier ones. Blank lines and lines starting with
+    // `//` are ignored.
+    #[derive(Clone,Debug)]
+    pub struct Rules {
+        names: Vec<String>,
+        start: Tool,
+        finish: Option<Tool>,
+        allowed: [Vec<Tool>; 3],
+        move_cost: Vec<usize>,
+        // switch_cost[from][to]:
+        switch_cost: Vec<Vec<usize>>
+    }
+    impl Default for Rules {
+        fn default() -> Rules {
+            Rules::from_str(PUZZLE_RULES).expect("built-in rules are valid")
+        }
+    }
+    impl Rules {
+        pub fn from_str(s: &str) -> Result<Rules> {
+            let mut lines = s.lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with("//"));
+
+            let tools = lines.next().ok_or_else(|| err!("Rules are empty"))?;
+            let mut words = tools.split_whitespace();
+            if words.next() != Some("tools") {
+                return Err(err!("Rules should begin with a 'tools' line, not '{}'", tools));
+            }
+            let mut names: Vec<String> = Vec::new();
+            for word in words {
+                if names.iter().any(|n| n == word) {
+                    return Err(err!("Tool '{}' is declared twice", word));
+                }
+                names.push(word.to_owned());
+            }
+            if names.is_empty() {
+                return Err(err!("At least one tool should be declared"));
+            }
+
+            let n = names.len();
+            let mut rules = Rules {
+                names,
+                start: 0,
+                finish: None,
+                allowed: [Vec::new(), Vec::new(), Vec::new()],
+                move_cost: vec![1; n],
+                switch_cost: vec![vec![0; n]; n]
+            };
+            let mut started = false;
+            for line in lines {
+                rules.parse_line(line, &mut started)
+                    .map_err(|e| err!("Invalid rule '{}': {}", line, e))?;
+            }
+            if !started {
+                return Err(err!("Rules should say which tool to 'start' with"));
+            }
+            Ok(rules)
+        }
+        pub fn num_tools(&self) -> usize {
+            self.names.len()
+        }
+        pub fn tool_name(&self, tool: Tool) -> &str {
+            &self.names[tool]
+        }
+        pub fn start(&self) -> Tool {
+            self.start
+        }
+        pub fn finish(&self) -> Option<Tool> {
+            self.finish
+        }
+        pub fn allowed(&self, ty: Type) -> &[Tool] {
+            &self.allowed[ty as usize]
+        }
+        pub fn can_use(&self, ty: Type, tool: Tool) -> bool {
+            self.allowed(ty).contains(&tool)
+        }
+        pub fn move_cost(&self, tool: Tool) -> usize {
+            self.move_cost[tool]
+        }
+        pub fn switch_cost(&self, from: Tool, to: Tool) -> usize {
+            self.switch_cost[from][to]
+        }
+        // The least time any move takes:
+        pub fn min_move_cost(&self) -> usize {
+            self.move_cost.iter().cloned().min().unwrap_or(0)
+        }
+        // The least time it could take to switch to the finishing tool, from
+        // a tool that isn't it:
+        pub fn min_finish_cost(&self) -> usize {
+            match self.finish {
+                Some(finish) => (0..self.names.len())
+                    .filter(|&t| t != finish)
+                    .map(|t| self.switch_cost[t][finish])
+                    .min()
+                    .unwrap_or(0),
+                None => 0
+            }
+        }
+        fn parse_line(&mut self, line: &str, started: &mut bool) -> Result<()> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match (words[0], &words[1..]) {
+                ("start", &[tool]) => {
+                    self.start = self.parse_tool(tool)?;
+                    *started = true;
+                },
+                ("finish", &[tool]) => {
+                    self.finish = Some(self.parse_tool(tool)?);
+                },
+                ("allow", &[region, ref tools @ ..]) => {
+                    let ty = parse_region(region)?;
+                    let tools = tools.iter().map(|t| self.parse_tool(t)).collect::<Result<_>>()?;
+                    self.allowed[ty as usize] = tools;
+                },
+                ("move", &[cost]) => {
+                    let cost = cost.parse()?;
+                    self.move_cost.iter_mut().for_each(|c| *c = cost);
+                },
+                ("move", &[tool, cost]) => {
+                    let tool = self.parse_tool(tool)?;
+                    self.move_cost[tool] = cost.parse()?;
+                },
+                ("switch", &[cost]) => {
+                    let cost = cost.parse()?;
+                    for (from, costs) in self.switch_cost.iter_mut().enumerate() {
+                        for (to, c) in costs.iter_mut().enumerate() {
+                            if from != to { *c = cost }
+                        }
+                    }
+                },
+                ("switch", &[from, to, cost]) => {
+                    let (from, to) = (self.parse_tool(from)?, self.parse_tool(to)?);
+                    if from == to {
+                        return Err(err!("can't switch from a tool to itself"));
+                    }
+                    self.switch_cost[from][to] = cost.parse()?;
+                },
+                _ => return Err(err!("unrecognised rule"))
+            }
+            Ok(())
+        }
+        fn parse_tool(&self, word: &str) -> Result<Tool> {
+            self.names.iter().position(|n| n == word)
+                .ok_or_else(|| err!("'{}' is not a declared tool", word))
+        }
+    }
 
+    fn parse_region(word: &str) -> Result<Type> {
+        match word {
+            "rocky" => Ok(Type::Rocky),
+            "wet" => Ok(Type::Wet),
+            "narrow" => Ok(Type::Narrow),
+            _ => Err(err!("'{}' is not a region type", word))
+        }
+    }
 }
 
 // Given a Cave, this module is reposnsible for
 // finding the fastest path to the target:
 mod solver {
     use crate::cave::Cave;
-    use crate::cave::Type::*;
-    use self::Tool::*;
+    use crate::rules::{Rules, Tool};
     use std::cmp::Reverse;
-    use std::collections::HashMap;
+    use std::collections::{BinaryHeap, HashMap};
 
     pub struct Solver<'a> {
         cave: &'a Cave,
-        current: Vec<State>,
-        visited: HashMap<((usize,usize),Tool), usize>,
-        best_time: usize
+        rules: Rules,
+        // Any route through a square further out than this is provably slower
+        // than a route we already know about, so we never go beyond it:
+        max: Option<(usize,usize)>
     }
 
     impl <'a> Solver<'a> {
-        pub fn new(cave: &Cave) -> Solver {
-            let s = State::starting();
-            let d = manhatten_distance(s.position, cave.target());
-            let mut visited = HashMap::new();
-            visited.insert((s.position,s.tool), s.time_spent);
-            Solver {
-                cave,
-                visited,
-                current: vec![s],
-                best_time: d * 8, // 1 + 7 to move and change tool every turn.
-            }
-        }
-        pub fn solve(&mut self) -> usize {
-            while self.step() { }
-            self.best_time
-        }
-        fn step(&mut self) -> bool {
-            // Get next state, bailing if none:
-            let first = match self.current.pop() {
-                Some(s) => s,
-                None => { return false }
+        #[allow(dead_code)]
+        pub fn new(cave: &'a Cave) -> Solver<'a> {
+            Solver::with_rules(cave, Rules::default())
+        }
+        pub fn with_rules(cave: &'a Cave, rules: Rules) -> Solver<'a> {
+            // A route visiting (x,y) takes at least as long as the distance to
+            // it plus the distance from it to the target (times the cheapest
+            // move). If x is beyond the target, that's x + (x - tx), so if that
+            // costs more than some known route, no route visiting x can beat it:
+            let target = cave.target();
+            let min_move = rules.min_move_cost();
+            let max = match straight_route_time(cave, &rules) {
+                Some(bound) if min_move > 0 => {
+                    let bound = bound / min_move;
+                    Some(((bound + target.0) / 2, (bound + target.1) / 2))
+                },
+                _ => None
             };
+            Solver { cave, rules, max }
+        }
+        pub fn rules(&self) -> &Rules {
+            &self.rules
+        }
+        // A* search, always expanding whichever state has the lowest time
+        // taken plus minimum time left to go (and then the least left to go).
+        // If we can't work out a bound on the search, and the target can't be
+        // reached, this will never return.
+        pub fn solve(&self) -> Option<Route> {
+            let start = State::starting(self.rules.start());
+            if !self.rules.can_use(self.cave.get(start.position), start.tool) {
+                return None;
+            }
+            if let Some(finish) = self.rules.finish() {
+                if !self.rules.can_use(self.cave.get(self.cave.target()), finish) {
+                    return None;
+                }
+            }
 
-            let target = self.cave.target();
-
-            // Keep exploring possible moves if they havent finished and
-            // still have a chance to exceed the best time:
-            for s in first.possible_moves(self.cave) {
-
-                // // This line speeds the solver up about 10x (~250ms) but no longer
-                // // absolutely guarantees a solution, so it's commented out:
-                // if s.position.0 > target.0 * 3 || s.position.1 > target.1 * 3 {
-                //     continue;
-                // }
+            let mut best: HashMap<Node, usize> = HashMap::new();
+            let mut came_from: HashMap<Node, Node> = HashMap::new();
+            let mut queue = BinaryHeap::new();
+            best.insert(start.node(), 0);
+            queue.push(Reverse((self.heuristic(&start), start)));
 
-                // Ignore potential states that could never beat the best:
-                let distance = manhatten_distance(s.position, target);
-                if distance + s.time_spent >= self.best_time {
+            while let Some(Reverse((_, s))) = queue.pop() {
+                // Skip states we've since found a faster way to:
+                if best.get(&s.node()).is_some_and(|&t| t < s.time_spent) {
                     continue;
                 }
-
-                // We're done! have we beaten the best?
-                if distance == 0 && s.tool == Torch {
-                    self.best_time = self.best_time.min(s.time_spent);
-                    continue;
+                // The heuristic never overestimates, so the first time we
+                // pop the target holding the right tool, that's the best route:
+                if s.position == self.cave.target() && self.rules.finish().is_none_or(|t| t == s.tool) {
+                    return Some(Route::from_nodes(s.node(), s.time_spent, &came_from, &best));
                 }
-
-                // If this state is visiting a square we've made it to
-                // with a lower score, ignore it:
-                let last_seen = self.visited
-                    .entry((s.position,s.tool))
-                    .or_insert(std::usize::MAX);
-                if *last_seen <= s.time_spent {
-                    continue;
+                for next in s.possible_moves(self.cave, &self.rules) {
+                    if let Some(max) = self.max {
+                        if next.position.0 > max.0 || next.position.1 > max.1 {
+                            continue;
+                        }
+                    }
+                    let last_seen = best.entry(next.node()).or_insert(usize::MAX);
+                    if *last_seen <= next.time_spent {
+                        continue;
+                    }
+                    *last_seen = next.time_spent;
+                    came_from.insert(next.node(), s.node());
+                    queue.push(Reverse((self.heuristic(&next), next)));
                 }
-                *last_seen = s.time_spent;
-
-                // This state has a chance, so add it to the list!
-                self.current.push(s);
+            }
+            None
+        }
+        // The least time it could possibly take to finish from some state,
+        // alongside the time taken so far, as a key to order states by:
+        fn heuristic(&self, s: &State) -> (usize, usize) {
+            let switch = match self.rules.finish() {
+                Some(finish) if finish != s.tool => self.rules.min_finish_cost(),
+                _ => 0
+            };
+            let distance = manhatten_distance(s.position, self.cave.target());
+            let left = distance * self.rules.min_move_cost() + switch;
+            (s.time_spent + left, left)
+        }
+    }
 
+    // The time taken to go straight across and then straight down to the
+    // target, switching tools only when we have to (but picking the best
+    // ones to switch to). This gives us an upper bound on the best time, if
+    // such a route is possible at all:
+    fn straight_route_time(cave: &Cave, rules: &Rules) -> Option<usize> {
+        let (tx,ty) = cave.target();
+        let mut path = (0..=tx).map(|x| (x,0)).chain((1..=ty).map(|y| (tx,y)));
+        let mut times: Vec<Option<usize>> = vec![None; rules.num_tools()];
+        times[rules.start()] = Some(0);
+        let mut here = path.next().unwrap();
+        loop {
+            // Switch to whichever tools can be used here as cheaply as possible
+            // (a few times over, in case switching via another tool is quicker):
+            let allowed = rules.allowed(cave.get(here));
+            for _ in 0..allowed.len() {
+                for &from in allowed {
+                    for &to in allowed {
+                        if let Some(t) = times[from] {
+                            let t = t + rules.switch_cost(from, to);
+                            if times[to].is_none_or(|curr| t < curr) {
+                                times[to] = Some(t);
+                            }
+                        }
+                    }
+                }
+            }
+            let next = match path.next() {
+                Some(next) => next,
+                None => break
+            };
+            // Then move on holding any tool that can be used in both places:
+            let next_ty = cave.get(next);
+            for (tool, time) in times.iter_mut().enumerate() {
+                *time = match *time {
+                    Some(t) if allowed.contains(&tool) && rules.can_use(next_ty, tool) => Some(t + rules.move_cost(tool)),
+                    _ => None
+                };
             }
+            here = next;
+        }
+        match rules.finish() {
+            Some(finish) => times[finish],
+            None => times.into_iter().flatten().min()
+        }
+    }
 
-            // Sort by lowest distance+time-spent-so-far (reversed because
-            // we want to order best things to the back to pop them off). We want
-            // to get to the end ASAP to constrain which other moves are likely,
-            // so we sort in order to make this happen as quickly as possible.
-            self.current.sort_unstable_by_key(|s| {
-                let distance = manhatten_distance(s.position, target);
-                Reverse(distance + s.time_spent)
-            });
+    // ### The fastest route to the target ###
+    #[derive(Debug,Clone,PartialEq,Eq)]
+    pub struct Route {
+        pub time: usize,
+        pub steps: Vec<Step>
+    }
 
-            true
-        }
+    // Each step taken, and the time at which it's been done:
+    #[derive(Debug,Clone,Copy,PartialEq,Eq)]
+    pub enum Step {
+        Move { time: usize, to: (usize,usize) },
+        Switch { time: usize, to: Tool }
     }
 
-    #[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
-    enum Tool {
-        NoTool,
-        Torch,
-        ClimbingGear
+    impl Route {
+        fn from_nodes(end: Node, time: usize, came_from: &HashMap<Node,Node>, times: &HashMap<Node,usize>) -> Route {
+            let mut steps = Vec::new();
+            let mut node = end;
+            while let Some(&prev) = came_from.get(&node) {
+                let time = times[&node];
+                steps.push(if prev.0 == node.0 {
+                    Step::Switch { time, to: node.1 }
+                } else {
+                    Step::Move { time, to: node.0 }
+                });
+                node = prev;
+            }
+            steps.reverse();
+            Route { time, steps }
+        }
     }
 
-    #[derive(Debug,Clone,Copy)]
+    // A position and the tool being held there:
+    type Node = ((usize,usize), Tool);
+
+    #[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
     struct State {
-        tool: Tool,
         time_spent: usize,
-        position: (usize,usize)
+        position: (usize,usize),
+        tool: Tool
     }
 
     impl State {
-        fn starting() -> State {
+        fn starting(tool: Tool) -> State {
             State {
-                tool: Torch,
+                tool,
                 time_spent: 0,
                 position: (0,0)
             }
         }
-        fn possible_moves(&self, cave: &Cave) -> Vec<State> {
+        fn node(&self) -> Node {
+            (self.position, self.tool)
+        }
+        fn possible_moves(&self, cave: &Cave, rules: &Rules) -> Vec<State> {
             let mut moves = Vec::with_capacity(5);
             let ty = cave.get(self.position);
-            
+
             // We can change tool to applicable ones:
-            let next_possible_tool = match (ty, self.tool) {
-                (Rocky, Torch) => ClimbingGear,
-                (Rocky, ClimbingGear) => Torch,
-                (Wet, NoTool) => ClimbingGear,
-                (Wet, ClimbingGear) => NoTool,
-                (Narrow, NoTool) => Torch,
-                (Narrow, Torch) => NoTool,
-                _ => panic!("We shouldn't be using {:?} in {:?} at {:?}", 
-                     self.tool, ty, self.position)
-            };
-            moves.push(State {
-                tool: next_possible_tool,
-                time_spent: self.time_spent + 7,
-                position: self.position
-            });
+            for &tool in rules.allowed(ty) {
+                if tool != self.tool {
+                    moves.push(State {
+                        tool,
+                        time_spent: self.time_spent + rules.switch_cost(self.tool, tool),
+                        position: self.position
+                    });
+                }
+            }
 
             // We can move to applicable surrounding squares:
             for pos in surrounding_coords(self.position) {
-                let nty = cave.get(pos);
-                let nstate = State {
-                    tool: self.tool,
-                    time_spent: self.time_spent + 1,
-                    position: pos
-                };
-                match (nty, self.tool) {
-                    (Rocky,Torch)  | (Rocky,ClimbingGear) |
-                    (Wet,NoTool)   | (Wet,ClimbingGear)   |
-                    (Narrow,Torch) | (Narrow,NoTool)     => moves.push(nstate),
-                    _ => { continue }
+                if rules.can_use(cave.get(pos), self.tool) {
+                    moves.push(State {
+                        tool: self.tool,
+                        time_spent: self.time_spent + rules.move_cost(self.tool),
+                        position: pos
+                    });
                 }
-            } 
+            }
 
             moves
         }
@@ -189,66 +534,387 @@ mod solver {
     }
 
     fn manhatten_distance((x1,y1): (usize,usize), (x2,y2): (usize,usize)) -> usize {
-        let x = if x1 > x2 { x1 - x2 } else { x2 - x1 };
-        let y = if y1 > y2 { y1 - y2 } else { y2 - y1 };
-        x + y
+        x1.abs_diff(x2) + y1.abs_diff(y2)
     }
 }
 
 // This allows us to find out what any given piece of cave looks like:
 mod cave {
-    use std::collections::HashMap;
-    use std::cell::RefCell;
+    use std::sync::{PoisonError, RwLock};
 
+    // ### A cave, whose erosion levels are worked out as far as they're needed ###
+    //
+    // Erosion levels are kept in a dense table, filled in a row at a time, since
+    // each one depends on those to the left and above it. Asking about somewhere
+    // outside of the table grows it to (at least) double the size in each
+    // direction that it's too small in. The table is behind a lock so that a
+    // Cave can be shared between threads.
     pub struct Cave {
-        // Cache known erosions to avoid recalculation:
-        erosions: RefCell<HashMap<(usize,usize), usize>>,
         depth: usize,
-        target: (usize,usize)
+        target: (usize,usize),
+        erosion: RwLock<Erosion>
     }
 
     impl Cave {
         pub fn new(depth: usize, target: (usize,usize)) -> Cave {
+            let erosion = Erosion::new(depth, target, target.0 + 1, target.1 + 1);
             Cave {
-                erosions: RefCell::new(HashMap::new()),
                 depth,
-                target
+                target,
+                erosion: RwLock::new(erosion)
             }
         }
         pub fn target(&self) -> (usize,usize) {
             self.target
         }
-        pub fn get(&self, (x,y):(usize,usize)) -> Type {
-            match self.get_erosion(x,y) % 3 {
+        pub fn get(&self, pos: (usize,usize)) -> Type {
+            match self.erosion(pos) % 3 {
                 0 => Type::Rocky,
                 1 => Type::Wet,
                 _ => Type::Narrow
             }
         }
-        fn get_erosion(&self, x: usize, y: usize) -> usize {
-            // return cached value if tis cached:
-            if let Some(&e) = self.erosions.borrow().get(&(x,y)) {
+        pub fn erosion(&self, (x,y): (usize,usize)) -> usize {
+            if let Some(e) = self.erosion.read().unwrap_or_else(PoisonError::into_inner).get(x,y) {
                 return e;
             }
+            let mut erosion = self.erosion.write().unwrap_or_else(PoisonError::into_inner);
+            // Someone else may have grown the table while we waited:
+            if let Some(e) = erosion.get(x,y) {
+                return e;
+            }
+            let width = if x < erosion.width { erosion.width } else { (x + 1).max(erosion.width * 2) };
+            let height = if y < erosion.height { erosion.height } else { (y + 1).max(erosion.height * 2) };
+            *erosion = Erosion::new(self.depth, self.target, width, height);
+            erosion.get(x,y).unwrap()
+        }
+        // The sum of the types of every region from the mouth to the target:
+        pub fn risk_level(&self) -> usize {
+            let (tx,ty) = self.target;
+            (0..=ty)
+                .flat_map(|y| (0..=tx).map(move |x| (x,y)))
+                .map(|pos| self.get(pos) as usize)
+                .sum()
+        }
+    }
 
-            // calculate value, cache and return it:
-            let geologic_index = if (x,y) == (0,0) {
-                0
-            } else if (x,y) == self.target {
-                0
-            } else if y == 0 {
-                x * 16807
-            } else if x == 0 {
-                y * 48271
+    // Erosion levels (which are always below 20183) for every position in
+    // some width and height, one row after the other:
+    struct Erosion {
+        width: usize,
+        height: usize,
+        values: Vec<u16>
+    }
+
+    impl Erosion {
+        fn new(depth: usize, target: (usize,usize), width: usize, height: usize) -> Erosion {
+            let mut values: Vec<u16> = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    let geologic_index = if (x,y) == (0,0) || (x,y) == target {
+                        0
+                    } else if y == 0 {
+                        x * 16807
+                    } else if x == 0 {
+                        y * 48271
+                    } else {
+                        values[y * width + x - 1] as usize * values[(y - 1) * width + x] as usize
+                    };
+                    values.push(((geologic_index + depth) % 20183) as u16);
+                }
+            }
+            Erosion { width, height, values }
+        }
+        fn get(&self, x: usize, y: usize) -> Option<usize> {
+            if x < self.width && y < self.height {
+                Some(self.values[y * self.width + x] as usize)
             } else {
-                self.get_erosion(x-1,y) * self.get_erosion(x,y-1)
-            };
-            let erosion = (geologic_index + self.depth) % 20183;
-            self.erosions.borrow_mut().insert((x,y), erosion);
-            erosion
+                None
+            }
         }
     }
 
     #[derive(Copy,Clone,Debug,PartialEq,Eq)]
     pub enum Type { Rocky, Wet, Narrow }
-}
\ No newline at end of file
+}
+
+// Draw the cave, with the route through it on top:
+mod render {
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::BufWriter;
+    use crate::cave::{Cave, Type};
+    use crate::rules::{Rules, Tool};
+    use crate::solver::{Route, Step};
+    use crate::Result;
+
+    // How many squares beyond the route and target to draw:
+    const MARGIN: usize = 5;
+    // How many pixels wide and tall each square is in a PNG:
+    const SCALE: usize = 4;
+
+    // Colours for rocky, wet and narrow regions, the mouth and the target,
+    // followed by the colours each tool is drawn in (reused if there are many):
+    const PALETTE: [[u8; 3]; 10] = [
+        [0x9a, 0x9a, 0x9a],
+        [0x5b, 0x8d, 0xd6],
+        [0x6b, 0x4a, 0x2e],
+        [0xff, 0xff, 0xff],
+        [0x00, 0x00, 0x00],
+        [0x30, 0xd0, 0x30],
+        [0xff, 0xc0, 0x00],
+        [0xe0, 0x30, 0x30],
+        [0xd0, 0x40, 0xd0],
+        [0x30, 0xd0, 0xd0]
+    ];
+    const TOOL_COLOURS: usize = 5;
+    // Terminal colours for each tool, in the same order:
+    const ANSI: [&str; TOOL_COLOURS] = ["32", "33", "31", "35", "36"];
+
+    // What's drawn at each square; a region, the mouth or target, or the
+    // route passing through holding some tool:
+    #[derive(Clone,Copy,PartialEq,Eq)]
+    enum Square {
+        Region(Type),
+        Mouth,
+        Target,
+        Route(Tool)
+    }
+
+    // The tool being held at each position on the route (if a tool is
+    // switched somewhere, the one we leave with):
+    fn held_tools(route: &Route, start: Tool) -> HashMap<(usize,usize), Tool> {
+        let mut tools = HashMap::new();
+        let mut pos = (0,0);
+        tools.insert(pos, start);
+        for step in &route.steps {
+            match *step {
+                Step::Move { to, .. } => {
+                    let tool = tools[&pos];
+                    pos = to;
+                    tools.insert(pos, tool);
+                },
+                Step::Switch { to, .. } => {
+                    tools.insert(pos, to);
+                }
+            }
+        }
+        tools
+    }
+
+    // Every square to draw, row by row:
+    fn squares(cave: &Cave, route: &Route, start: Tool) -> Vec<Vec<Square>> {
+        let tools = held_tools(route, start);
+        let target = cave.target();
+        let width = tools.keys().map(|p| p.0).chain(Some(target.0)).max().unwrap() + MARGIN + 1;
+        let height = tools.keys().map(|p| p.1).chain(Some(target.1)).max().unwrap() + MARGIN + 1;
+        (0..height).map(|y| (0..width).map(|x| {
+            match tools.get(&(x,y)) {
+                _ if (x,y) == (0,0) => Square::Mouth,
+                _ if (x,y) == target => Square::Target,
+                Some(&tool) => Square::Route(tool),
+                None => Square::Region(cave.get((x,y)))
+            }
+        }).collect()).collect()
+    }
+
+    fn glyph(ty: Type) -> char {
+        match ty {
+            Type::Rocky => '.',
+            Type::Wet => '=',
+            Type::Narrow => '|'
+        }
+    }
+
+    // The character marking the route while holding each tool; the first
+    // letter of its name, unless that doesn't tell the tools apart (or looks
+    // like something else on the map), in which case its number:
+    fn markers(rules: &Rules) -> Vec<char> {
+        let initials: Vec<char> = (0..rules.num_tools())
+            .map(|tool| rules.tool_name(tool).chars().next().unwrap_or('?'))
+            .collect();
+        let distinct = initials.iter().enumerate().all(|(n, c)| {
+            !initials[..n].contains(c) && !".=|MT".contains(*c)
+        });
+        if distinct {
+            initials
+        } else {
+            (0..rules.num_tools())
+                .map(|tool| std::char::from_digit(tool as u32, 36).unwrap_or('?'))
+                .collect()
+        }
+    }
+
+    // The cave as text, with the route marked by the tool held at each square
+    // (see `markers`). If `colour` is set, the route is also coloured using
+    // terminal escape codes:
+    pub fn ascii(cave: &Cave, route: &Route, rules: &Rules, colour: bool) -> String {
+        let markers = markers(rules);
+        let mut out = String::new();
+        for row in squares(cave, route, rules.start()) {
+            for square in row {
+                match square {
+                    Square::Region(ty) => out.push(glyph(ty)),
+                    Square::Mouth => out.push('M'),
+                    Square::Target => out.push('T'),
+                    Square::Route(tool) if colour => {
+                        out.push_str(&format!("\x1b[1;{}m{}\x1b[0m", ANSI[tool % TOOL_COLOURS], markers[tool]));
+                    },
+                    Square::Route(tool) => out.push(markers[tool])
+                }
+            }
+            out.push('\n');
+        }
+        let key: Vec<String> = (0..rules.num_tools())
+            .map(|tool| format!("{} = {}", markers[tool], rules.tool_name(tool)))
+            .collect();
+        out.push_str(&format!("Route: {}\n", key.join(", ")));
+        out
+    }
+
+    pub fn write_png(path: &str, cave: &Cave, route: &Route, start: Tool) -> Result<()> {
+        let squares = squares(cave, route, start);
+        let (width, height) = (squares[0].len(), squares.len());
+        let mut pixels = Vec::with_capacity(width * height * SCALE * SCALE);
+        for row in &squares {
+            let row: Vec<u8> = row.iter().flat_map(|&square| {
+                let idx = match square {
+                    Square::Region(ty) => ty as u8,
+                    Square::Mouth => 3,
+                    Square::Target => 4,
+                    Square::Route(tool) => (5 + tool % TOOL_COLOURS) as u8
+                };
+                std::iter::repeat_n(idx, SCALE)
+            }).collect();
+            for _ in 0..SCALE {
+                pixels.extend_from_slice(&row);
+            }
+        }
+
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, (width * SCALE) as u32, (height * SCALE) as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(PALETTE.concat());
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(())
+    }
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_input("depth: 510\ntarget: 10,10\n").unwrap(), (510, (10,10)));
+        assert!(parse_input("depth: 510").is_err());
+        assert!(parse_input("depth: 510\ntarget: 10").is_err());
+    }
+
+    #[test]
+    fn ascii_route() {
+        let cave = Cave::new(510, (10,10));
+        let solver = Solver::new(&cave);
+        let route = solver.solve().unwrap();
+
+        // The route is marked by the tool held, without colour unless asked for:
+        let plain = render::ascii(&cave, &route, solver.rules(), false);
+        assert!(!plain.contains('\x1b'));
+        assert!(plain.starts_with("M=.|=.|.|=.|=|=.\ntnnnc|||..|.=...\n.==|c...||=..|==\n"));
+        assert!(plain.ends_with("Route: n = neither, t = torch, c = climbing-gear\n"));
+        let coloured = render::ascii(&cave, &route, solver.rules(), true);
+        assert!(coloured.contains("\x1b[1;"));
+
+        // Tools that share a first letter are numbered instead:
+        let rules = Rules::from_str("
+            tools neither torch tape
+            start torch
+            finish torch
+            allow rocky torch tape
+            allow wet tape neither
+            allow narrow torch neither
+            switch 7
+        ").unwrap();
+        let solver = Solver::with_rules(&cave, rules);
+        let route = solver.solve().unwrap();
+        let plain = render::ascii(&cave, &route, solver.rules(), false);
+        assert!(plain.starts_with("M=.|=.|.|=.|=|=.\n10002|||..|.=...\n"));
+        assert!(plain.ends_with("Route: 0 = neither, 1 = torch, 2 = tape\n"));
+    }
+
+    #[test]
+    fn example_route() {
+        let cave = Cave::new(510, (10,10));
+        let solver = Solver::new(&cave);
+        let route = solver.solve().unwrap();
+        assert_eq!(route.time, 45);
+
+        // Every step should take the right amount of time, and the route
+        // should end up at the target holding the torch:
+        let mut last = 0;
+        let mut pos = (0,0);
+        for step in &route.steps {
+            match *step {
+                Step::Move { time, to } => { assert_eq!(time, last + 1); last = time; pos = to; },
+                Step::Switch { time, .. } => { assert_eq!(time, last + 7); last = time; }
+            }
+        }
+        assert_eq!(last, 45);
+        assert_eq!(pos, (10,10));
+        match route.steps.last() {
+            Some(&Step::Switch { to, .. }) => assert_eq!(solver.rules().tool_name(to), "torch"),
+            step => panic!("expected to end by switching to the torch, not {:?}", step)
+        }
+    }
+
+    #[test]
+    fn example_cave() {
+        let cave = Cave::new(510, (10,10));
+        assert_eq!(cave.risk_level(), 114);
+        assert_eq!(cave.erosion((1,1)), 1805);
+
+        // Growing far out on other threads gives the same answers as growing
+        // a bit at a time here:
+        let other = Cave::new(510, (10,10));
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let other = &other;
+                s.spawn(move || other.get((300 + t * 50, 500)));
+            }
+        });
+        for y in 0..=500 {
+            for x in 0..=450 {
+                assert_eq!(cave.get((x,y)), other.get((x,y)));
+            }
+        }
+    }
+
+    #[test]
+    fn custom_rules() {
+        let cave = Cave::new(510, (10,10));
+        let rules = |s| Rules::from_str(s).unwrap();
+
+        // If any tool goes anywhere, it's just the distance to the target:
+        let anything = rules("tools hands\nstart hands\nallow rocky hands\nallow wet hands\nallow narrow hands\nmove 3");
+        assert_eq!(Solver::with_rules(&cave, anything).solve().unwrap().time, 60);
+
+        // The puzzle rules, but with free switching; no tool is needed at
+        // the end, so it's the distance to the target again:
+        let free = rules("
+            tools neither torch climbing-gear
+            start torch
+            allow rocky torch climbing-gear
+            allow wet climbing-gear neither
+            allow narrow torch neither
+            switch 0
+        ");
+        assert_eq!(Solver::with_rules(&cave, free).solve().unwrap().time, 20);
+
+        assert!(Rules::from_str("tools a\nstart b").is_err());
+        assert!(Rules::from_str("tools a\nallow rocky a").is_err());
+    }
+}
@@ -1,19 +1,26 @@
 use regex::Regex;
 use lazy_static::lazy_static;
-use rayon::prelude::*;
+use manhattan::{Ball, BoundingBox, Point};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::result;
 use std::error::Error;
 
-macro_rules! err { ($($tt:tt)*) => { Box::<Error>::from(format!($($tt)*)) } }
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
 type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
 
+// Usage: day23 <input> [--members]
+//
+// With --members, the line number of every sphere in the largest set of
+// mutually overlapping spheres is printed.
 fn main() -> Result<()> {
 
     let filename = std::env::args().nth(1).expect("need puzzle input");
+    let show_members = std::env::args().skip(2).any(|a| a == "--members");
     let input: Result<Vec<Sphere>> = std::fs::read_to_string(filename)
         .expect("can't open file")
         .lines()
-        .map(Sphere::from_str)
+        .map(parse_sphere)
         .collect();
 
     let spheres = input?;
@@ -28,108 +35,332 @@ fn main() -> Result<()> {
         .unwrap();
     let largest_overlaps = spheres
         .iter()
-        .filter(|s| largest_sphere.in_range(&s.position))
+        .filter(|s| largest_sphere.contains(&s.centre))
         .count();
     println!("Star 1: {}", largest_overlaps);
 
-    // For star 2, find the largest set of total overlapping spheres. Ignore
-    // spheres that contain other spheres in the list. Then grow a sphere out
-    // from (0,0,0) until it overlaps with all of them.
-    let spheres = largest_overlapping_set(&spheres)?;
-    let origin = Position { x:0, y:0, z:0 };
-    let furthest = spheres.iter()
-        .map(|s| s.position.distance(&origin) - s.radius)
-        .max().unwrap();
-
-    let mut overlapping_radii = (furthest..).filter(|&radius| {
-        let s = Sphere { position: origin, radius };
-        let num_overlapping = number_overlapping(&s, &spheres);
-        num_overlapping == spheres.len()
-    });
-    println!("Star 2: {}", overlapping_radii.next().unwrap());
+    // For star 2, find the position in range of the most spheres, and of
+    // those, the one closest to (0,0,0):
+    let best = best_position(&spheres).ok_or_else(|| err!("no spheres"))?;
+    let Point([x, y, z]) = best.position;
+    println!("Star 2: {} (at {},{},{}, in range of {} spheres)", best.distance, x, y, z, best.count);
+
+    // Separately, find the largest set of spheres that all overlap each other.
+    // Spheres overlapping in pairs doesn't mean there's anywhere in range of
+    // all of them at once, so check that too:
+    let members = clique::largest(&spheres);
+    let clique: Vec<Sphere> = members.iter().map(|&idx| spheres[idx]).collect();
+    match common_position(&clique) {
+        Some(Point([x, y, z])) => println!("Largest overlapping set: {} spheres, all in range of {},{},{}", clique.len(), x, y, z),
+        None => println!("Largest overlapping set: {} spheres, with nowhere in range of all of them", clique.len())
+    }
+    if show_members {
+        let lines: Vec<String> = members.iter().map(|idx| (idx + 1).to_string()).collect();
+        println!("Members (by line): {}", lines.join(","));
+    }
 
     Ok(())
 }
 
-fn number_overlapping(sphere: &Sphere, spheres: &[Sphere]) -> usize {
-    spheres.iter().filter(|s| s.overlaps_with(sphere)).count()
+// ### The best position found, how many spheres it's in and how far away it is ###
+#[derive(Debug,Clone,Copy,Eq,PartialEq)]
+struct Best {
+    position: Position,
+    count: usize,
+    distance: i64
 }
 
-fn largest_overlapping_set(spheres: &[Sphere]) -> Result<Vec<Sphere>> {
-    // Which spheres overlap with eachother? As an optimisation, we
-    // only compare later spheres in the list for overlap; The earliest
-    // in a list of overlaps will always have all of the potential
-    // overlaps in the group even if later ones do not.
-    let overlaps: Vec<Vec<Sphere>> = spheres.par_iter()
-        .enumerate()
-        .map(|(idx1,s1)| {
-            spheres[idx1+1 .. ].iter()
-                .filter(|s2| s1.overlaps_with(s2))
-                .cloned()
-                .collect()
-        })
-        .collect();
+// Branch and bound. Start with a cube around every sphere, and repeatedly split
+// the most promising cube into 8 smaller ones. The number of spheres that touch a
+// cube is at least the number that any position inside it is in range of, so if
+// we always pick the cube touching the most spheres (then the closest one to the
+// origin, then the smallest), the first single position we pick is in range of
+// as many spheres as any position can be, and no position in range of as many
+// is closer to the origin.
+fn best_position(spheres: &[Sphere]) -> Option<Best> {
+    let origin = Position::origin();
+    let mut cubes = BinaryHeap::new();
+    let push = |cubes: &mut BinaryHeap<_>, cube: Cube| {
+        let count = spheres.iter().filter(|s| cube.touches(s)).count();
+        cubes.push((count, Reverse(cube.distance(&origin)), Reverse(cube.size), cube));
+    };
+    push(&mut cubes, Cube::bounding(spheres)?);
 
-    // For each sphere, find the set of total overlaps (ie
-    // the fully connected sub-graph) from here, and pick the largest
-    // of these to return.
-    overlaps.into_par_iter().map(|os| {
-            let mut full: Vec<Sphere> = Vec::new();
-            for sphere in os {
-                let overlaps_with_everything = full
-                    .iter()
-                    .all(|s| s.overlaps_with(&sphere));
-                if overlaps_with_everything {
-                    full.push(sphere);
+    while let Some((count, Reverse(distance), _, cube)) = cubes.pop() {
+        if cube.size == 1 {
+            return Some(Best { position: cube.min, count, distance });
+        }
+        for smaller in cube.split() {
+            push(&mut cubes, smaller);
+        }
+    }
+    None
+}
+
+// A position in range of every sphere given, if there is one:
+fn common_position(spheres: &[Sphere]) -> Option<Position> {
+    match best_position(spheres) {
+        Some(best) if best.count == spheres.len() => Some(best.position),
+        _ => None
+    }
+}
+
+// ### A cube of positions, starting at some corner and some power of two wide ###
+#[derive(Debug,Clone,Copy,Eq,PartialEq,Ord,PartialOrd)]
+struct Cube {
+    min: Position,
+    size: i64
+}
+
+impl Cube {
+    // The smallest such cube around all of the positions in range of spheres:
+    fn bounding(spheres: &[Sphere]) -> Option<Cube> {
+        let bounds = spheres.iter().map(|s| s.bounds()).reduce(|a, b| a.union(&b))?;
+        let width = (0..3).map(|axis| bounds.width(axis)).max().unwrap();
+        Some(Cube { min: bounds.min, size: (width as u64).next_power_of_two() as i64 })
+    }
+    fn bounds(&self) -> BoundingBox<3> {
+        BoundingBox::new(self.min, self.min + Point([self.size - 1; 3]))
+    }
+    fn distance(&self, pos: &Position) -> i64 {
+        self.bounds().distance(pos)
+    }
+    fn touches(&self, sphere: &Sphere) -> bool {
+        sphere.intersects_box(&self.bounds())
+    }
+    fn split(&self) -> Vec<Cube> {
+        let half = self.size / 2;
+        let mut cubes = Vec::with_capacity(8);
+        for &dx in &[0, half] {
+            for &dy in &[0, half] {
+                for &dz in &[0, half] {
+                    cubes.push(Cube { min: self.min + Point([dx, dy, dz]), size: half });
                 }
             }
-            full
-        })
-        .max_by_key(|v| v.len())
-        .ok_or(err!("no spheres"))
+        }
+        cubes
+    }
 }
 
+// Spheres are all of the positions within some (Manhattan) distance of a centre:
+type Sphere = Ball<3>;
+type Position = Point<3>;
 
-#[derive(Debug,Clone,Copy,Eq,PartialEq)]
-struct Sphere {
-    position: Position,
-    radius: i64
+fn parse_sphere(s: &str) -> Result<Sphere> {
+    lazy_static!{
+        static ref re: Regex =
+            Regex::new(r"pos=<(-?\d+),(-?\d+),(-?\d+)>, r=(\d+)").unwrap();
+    }
+    let caps = re.captures(s).ok_or_else(|| err!("'{}' not a sphere", s))?;
+    let get = |n: usize| caps[n].parse::<i64>();
+    Ok(Ball::new(Point([get(1)?, get(2)?, get(3)?]), get(4)?))
 }
 
-impl Sphere {
-    fn from_str(s: &str) -> Result<Sphere> {
-        lazy_static!{
-            static ref re: Regex =
-                Regex::new(r"pos=<(-?\d+),(-?\d+),(-?\d+)>, r=(\d+)").unwrap();
+// Find the largest set of spheres that all overlap each other (a maximum
+// clique in the graph of overlapping spheres):
+mod clique {
+    use rayon::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::Sphere;
+
+    // The indexes of the spheres in the largest set, in ascending order. This
+    // uses Bron-Kerbosch with pivoting, running the search from each sphere
+    // in parallel (looking only at spheres after it, so that each set is only
+    // found once), and abandoning any search that can't beat the best so far.
+    pub fn largest(spheres: &[Sphere]) -> Vec<usize> {
+        let n = spheres.len();
+        let neighbours: Vec<Bits> = spheres.iter().enumerate().map(|(i,a)| {
+            let mut bits = Bits::new(n);
+            for (j,b) in spheres.iter().enumerate() {
+                if i != j && a.intersects(b) {
+                    bits.insert(j);
+                }
+            }
+            bits
+        }).collect();
+
+        // Start with a quick guess at the best (adding the most connected
+        // spheres first), so that hopeless searches can be abandoned early:
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by_key(|&v| std::cmp::Reverse(neighbours[v].len()));
+        let mut guess: Vec<usize> = Vec::new();
+        for v in order {
+            if guess.iter().all(|&u| neighbours[u].contains(v)) {
+                guess.push(v);
+            }
         }
-        let caps = re.captures(s).ok_or(err!("'{}' not a sphere", s))?;
-        let get = |n| caps.get(n).unwrap().as_str().parse().unwrap();
-        Ok(Sphere {
-            position: Position { x: get(1), y: get(2), z: get(3) },
-            radius: get(4)
-        })
+
+        let best_len = AtomicUsize::new(guess.len());
+        let mut best = (0..n).into_par_iter().filter_map(|v| {
+            let mut later = Bits::new(n);
+            (v+1..n).for_each(|j| later.insert(j));
+            let search = Search { neighbours: &neighbours, best_len: &best_len };
+            let mut best = None;
+            search.expand(&mut vec![v], neighbours[v].and(&later), Bits::new(n), &mut best);
+            best
+        }).chain(Some(guess)).max_by_key(|c: &Vec<usize>| c.len()).unwrap_or_default();
+        best.sort_unstable();
+        best
+    }
+
+    struct Search<'a> {
+        neighbours: &'a [Bits],
+        best_len: &'a AtomicUsize
     }
-    fn in_range(&self, other: &Position) -> bool {
-        self.position.distance(other) <= self.radius
+
+    impl <'a> Search<'a> {
+        // `clique` is the current set, `candidates` could each be added to it,
+        // and `excluded` could be too, but have already been tried:
+        fn expand(&self, clique: &mut Vec<usize>, candidates: Bits, mut excluded: Bits, best: &mut Option<Vec<usize>>) {
+            if candidates.is_empty() {
+                if excluded.is_empty() && clique.len() > self.best_len.load(Ordering::Relaxed) {
+                    self.best_len.fetch_max(clique.len(), Ordering::Relaxed);
+                    *best = Some(clique.clone());
+                }
+                return;
+            }
+            // Even adding every candidate won't beat the best, so give up:
+            if clique.len() + candidates.len() < self.best_len.load(Ordering::Relaxed) {
+                return;
+            }
+            // Any maximal set contains the pivot or something that doesn't
+            // overlap it, so only those need trying. Pick the pivot that
+            // overlaps the most candidates to leave the fewest to try:
+            let pivot = candidates.iter().chain(excluded.iter())
+                .max_by_key(|&u| candidates.and(&self.neighbours[u]).len())
+                .unwrap();
+            let mut candidates = candidates;
+            for v in candidates.and_not(&self.neighbours[pivot]).iter() {
+                clique.push(v);
+                self.expand(
+                    clique,
+                    candidates.and(&self.neighbours[v]),
+                    excluded.and(&self.neighbours[v]),
+                    best
+                );
+                clique.pop();
+                candidates.remove(v);
+                excluded.insert(v);
+            }
+        }
     }
-    fn overlaps_with(&self, other: &Sphere) -> bool {
-        let dist = self.position.distance(&other.position);
-        dist <= self.radius + other.radius
+
+    // ### A set of indexes, one bit each ###
+    #[derive(Clone,Debug)]
+    struct Bits(Vec<u64>);
+
+    impl Bits {
+        fn new(n: usize) -> Bits {
+            Bits(vec![0; n.div_ceil(64)])
+        }
+        fn insert(&mut self, i: usize) {
+            self.0[i / 64] |= 1 << (i % 64);
+        }
+        fn contains(&self, i: usize) -> bool {
+            self.0[i / 64] >> (i % 64) & 1 == 1
+        }
+        fn remove(&mut self, i: usize) {
+            self.0[i / 64] &= !(1 << (i % 64));
+        }
+        fn len(&self) -> usize {
+            self.0.iter().map(|w| w.count_ones() as usize).sum()
+        }
+        fn is_empty(&self) -> bool {
+            self.0.iter().all(|&w| w == 0)
+        }
+        fn and(&self, other: &Bits) -> Bits {
+            Bits(self.0.iter().zip(&other.0).map(|(a,b)| a & b).collect())
+        }
+        fn and_not(&self, other: &Bits) -> Bits {
+            Bits(self.0.iter().zip(&other.0).map(|(a,b)| a & !b).collect())
+        }
+        fn iter(&self) -> impl Iterator<Item=usize> + '_ {
+            self.0.iter().enumerate().flat_map(|(idx, &word)| {
+                (0..64).filter(move |bit| word >> bit & 1 == 1).map(move |bit| idx * 64 + bit)
+            })
+        }
     }
 }
 
-#[derive(Debug,Clone,Copy,Eq,PartialEq,Hash)]
-struct Position {
-    x: i64,
-    y: i64,
-    z: i64
-}
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spheres(s: &[((i64,i64,i64),i64)]) -> Vec<Sphere> {
+        s.iter().map(|&((x,y,z),radius)| Ball::new(Point([x, y, z]), radius)).collect()
+    }
+
+    // The approach this used to take; greedily build a set of overlapping
+    // spheres from each one in turn, and then grow a sphere from the origin
+    // until it overlaps everything in the largest set:
+    fn greedy(spheres: &[Sphere]) -> i64 {
+        let overlaps = |a: &Sphere, b: &Sphere| a.intersects(b);
+        let set = (0..spheres.len()).map(|idx| {
+            let mut full: Vec<Sphere> = Vec::new();
+            for s in spheres[idx+1..].iter().filter(|s| overlaps(&spheres[idx], s)) {
+                if full.iter().all(|f| overlaps(f, s)) {
+                    full.push(*s);
+                }
+            }
+            full
+        }).max_by_key(|v| v.len()).unwrap();
+        let origin = Position::origin();
+        let furthest = set.iter().map(|s| s.centre.distance(&origin) - s.radius).max().unwrap();
+        (furthest..).find(|&radius| {
+            let s = Ball::new(origin, radius);
+            set.iter().all(|o| overlaps(o, &s))
+        }).unwrap()
+    }
 
-impl Position {
-    fn distance(&self, other: &Position) -> i64 {
-        let d1 = (self.x - other.x).abs();
-        let d2 = (self.y - other.y).abs();
-        let d3 = (self.z - other.z).abs();
-        d1 + d2 + d3
+    #[test]
+    fn example() {
+        let s = spheres(&[
+            ((10,12,12),2), ((12,14,12),2), ((16,12,12),4),
+            ((14,14,14),6), ((50,50,50),200), ((10,10,10),5)
+        ]);
+        let best = best_position(&s).unwrap();
+        assert_eq!(best, Best { position: Point([12,12,12]), count: 5, distance: 36 });
+        assert_eq!(greedy(&s), 36);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn greedy_leaves_out_first_sphere() {
+        // Each set left out the sphere it was built from, so here the greedy
+        // approach only ever looks for somewhere in range of one sphere:
+        let s = spheres(&[((-2,0,4),1), ((0,0,3),2), ((3,3,-3),0)]);
+        let best = best_position(&s).unwrap();
+        assert_eq!((best.count, best.distance), (2, 5));
+        assert_eq!(greedy(&s), 1);
+    }
+
+    #[test]
+    fn greedy_depends_on_order() {
+        // The first sphere overlaps everything. Building a set from it picks
+        // up the second and third, which rules out the fourth and fifth, even
+        // though they're in range of the first and second at 10,0,0:
+        let s = spheres(&[((0,0,0),20), ((0,0,0),10), ((-6,0,0),1), ((10,0,0),1), ((11,0,0),1)]);
+        let best = best_position(&s).unwrap();
+        assert_eq!(best, Best { position: Point([10,0,0]), count: 4, distance: 10 });
+        assert_eq!(greedy(&s), 5);
+
+        // The largest set of overlapping spheres doesn't miss them:
+        let members = clique::largest(&s);
+        assert_eq!(members, vec![0,1,3,4]);
+        let clique: Vec<Sphere> = members.iter().map(|&idx| s[idx]).collect();
+        assert_eq!(common_position(&clique), Some(Point([10,0,0])));
+    }
+
+    #[test]
+    fn largest_clique() {
+        // Two separate groups; the larger one is picked even though it's later:
+        let s = spheres(&[
+            ((0,0,0),1), ((1,0,0),1), ((0,1,0),1),
+            ((50,0,0),2), ((52,0,0),2), ((50,2,0),2), ((51,1,0),1), ((80,0,0),1)
+        ]);
+        assert_eq!(clique::largest(&s), vec![3,4,5,6]);
+        assert_eq!(clique::largest(&[]), Vec::<usize>::new());
+    }
+}
@@ -2,158 +2,293 @@ use regex::Regex;
 use lazy_static::lazy_static;
 use std::result;
 use std::error::Error;
-use std::iter::repeat;
 use std::cmp::Reverse;
-use self::Weapon::*;
+use std::collections::{BTreeMap, HashMap};
 
-macro_rules! err { ($($tt:tt)*) => { Box::<Error>::from(format!($($tt)*)) } }
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
 type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
 
+// Usage: day24 <input> [--log] [--window N]
+//
+// With --log, every round of the star 1 fight is described in the same way
+// as the example in the puzzle. --window is how many boosts in a row below
+// the best one found must fail to win before we're happy it's the smallest.
+//
+// The input can contain any number of armies, and lines like
+// "Immune System is allied with Reinforcements" to stop two armies from
+// attacking each other. Every army fights every other army otherwise. As in
+// the puzzle, every group can only be picked as a target by one attacking
+// group each round, whichever army that attacker is in.
 fn main() -> Result<()> {
 
     let filename = std::env::args().nth(1).expect("need puzzle input");
+    let opts = parse_opts(std::env::args().skip(2))?;
     let input: String = std::fs::read_to_string(filename)
         .expect("can't open file");
 
-    let armies = parse_armies(&input)?;
+    let battle = parse_battle(&input)?;
 
     // For star 1, just run the fight and see what happens:
-    let (mut army1, mut army2) = armies.clone();
-    let remaining_units = fight(&mut army1, &mut army2).expect("fight got stuck");
+    let mut star1 = battle.clone();
+    let mut log = if opts.log { Log::new(&battle) } else { Log::none() };
+    let remaining_units = fight(&mut star1, &mut log).expect("fight got stuck");
+    if let Some(log) = log.into_string() {
+        print!("{}", log);
+    }
     println!("Star 1: {}", remaining_units);
 
-    // For star 2, apply boost to first army until it wins:
-    let mut remaining_units = 0;
-    for boost in 1.. {
-        let (mut army1, mut army2) = armies.clone();
-        // Apply boost to immune system:
-        army1.iter_mut().for_each(|g| g.attack_damage += boost);
-        // Ignore fights that get stuck, track remaining otherwise:
-        match fight(&mut army1, &mut army2) {
-            Some(remaining) => { remaining_units = remaining },
-            None => { continue }
-        }
-        // End when army1 has survived the fight:
-        if army1.len() > 0 {
-            break;
-        }
+    // For star 2, find the smallest boost to the first army that lets it win:
+    let mut search = BoostSearch::new(&battle);
+    let (boost, remaining_units) = search.smallest_win(opts.window)
+        .ok_or_else(|| err!("No boost lets the first army win"))?;
+    println!("Star 2: {} (with a boost of {})", remaining_units, boost);
+
+    let stalemates: Vec<String> = search.stalemates().map(|b| b.to_string()).collect();
+    if stalemates.is_empty() {
+        println!("None of the {} boosts tried ended in a stalemate", search.fights());
+    } else {
+        println!("{} of the {} boosts tried ended in a stalemate: {}",
+            stalemates.len(), search.fights(), stalemates.join(", "));
     }
-    println!("Star 2: {}", remaining_units);
 
     Ok(())
 }
 
+struct Opts {
+    log: bool,
+    window: usize
+}
+
+fn parse_opts(mut args: impl Iterator<Item=String>) -> Result<Opts> {
+    let mut opts = Opts { log: false, window: 20 };
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--log" => { opts.log = true },
+            "--window" => {
+                let v = args.next().ok_or_else(|| err!("Expected a value after '{}'", arg))?;
+                opts.window = v.parse()?;
+            },
+            _ => return Err(err!("Unexpected argument '{}'", arg))
+        }
+    }
+    Ok(opts)
+}
+
+// ### The result of a fight ###
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum Outcome {
+    Won(usize),
+    Lost(usize),
+    Stalemate
+}
+
+// ### Search for the smallest boost that lets the first army win ###
+//
+// More boost usually helps, but not always: a boost changes which targets
+// are picked and the order they're picked in, and that can lead to a loss
+// or a stalemate where a smaller boost would have won. So, we bracket the
+// answer assuming that more boost helps, and then look at the boosts just
+// below what we found in case any of them also win.
+struct BoostSearch<'a> {
+    battle: &'a Battle,
+    outcomes: BTreeMap<usize, Outcome>
+}
+
+impl <'a> BoostSearch<'a> {
+    fn new(battle: &'a Battle) -> BoostSearch<'a> {
+        BoostSearch { battle, outcomes: BTreeMap::new() }
+    }
+    // Fight with some boost to the first army, remembering the result.
+    // The first army wins if it (and so its allies) are the only ones left:
+    fn outcome(&mut self, boost: usize) -> Outcome {
+        let battle = self.battle;
+        *self.outcomes.entry(boost).or_insert_with(|| {
+            let mut battle = battle.clone();
+            battle.groups.values_mut()
+                .filter(|g| g.id.army == 0)
+                .for_each(|g| g.attack_damage += boost);
+            match fight(&mut battle, &mut Log::none()) {
+                None => Outcome::Stalemate,
+                Some(remaining) if battle.has_groups(0) => Outcome::Won(remaining),
+                Some(remaining) => Outcome::Lost(remaining)
+            }
+        })
+    }
+    fn wins(&mut self, boost: usize) -> bool {
+        matches!(self.outcome(boost), Outcome::Won(_))
+    }
+    // Return the smallest winning boost found and the units left after
+    // that fight. Once a winning boost is found, `window` boosts in a row
+    // below it must not win for us to stop looking for a smaller one.
+    fn smallest_win(&mut self, window: usize) -> Option<(usize, usize)> {
+        // Once a single unit with the boost could wipe out every army that
+        // the first army fights in one attack, more boost won't change anything:
+        let limit: usize = self.battle.groups.values()
+            .filter(|g| self.battle.hostile(0, g.id.army))
+            .map(|g| g.unit_count * g.unit_hp)
+            .sum();
+
+        // Double the boost until we win, then binary search between the last
+        // boost that didn't win and the first one that did:
+        let mut found = 0;
+        if !self.wins(0) {
+            let mut lo = 0;
+            let mut hi = 1;
+            while !self.wins(hi) {
+                if hi > limit {
+                    return None;
+                }
+                lo = hi;
+                hi *= 2;
+            }
+            while hi - lo > 1 {
+                let mid = lo + (hi - lo) / 2;
+                if self.wins(mid) { hi = mid } else { lo = mid }
+            }
+            found = hi;
+        }
+
+        // Make sure that no boost just below it wins too:
+        let mut boost = found;
+        let mut misses = 0;
+        while boost > 0 && misses < window {
+            boost -= 1;
+            if self.wins(boost) {
+                found = boost;
+                misses = 0;
+            } else {
+                misses += 1;
+            }
+        }
+
+        match self.outcome(found) {
+            Outcome::Won(remaining) => Some((found, remaining)),
+            _ => unreachable!("boost {} was found to win", found)
+        }
+    }
+    // Which of the boosts that we tried ended in a stalemate:
+    fn stalemates(&self) -> impl Iterator<Item=usize> + '_ {
+        self.outcomes.iter()
+            .filter(|(_,&o)| o == Outcome::Stalemate)
+            .map(|(&b,_)| b)
+    }
+    // How many fights we've had to run:
+    fn fights(&self) -> usize {
+        self.outcomes.len()
+    }
+}
+
 // Run fight to completion, returning remaining units. Returns None
 // if the fight got stuck and could not complete.
-fn fight(army1: &mut Army, army2: &mut Army) -> Option<usize> {
-    while army1.len() > 0 && army2.len() > 0 {
-        let army1_orders = target_seletion(army1, army2);
-        let army2_orders = target_seletion(army2, army1);
-        if !attack_phase((army1, army1_orders), (army2, army2_orders)) {
+fn fight(battle: &mut Battle, log: &mut Log) -> Option<usize> {
+    while !battle.is_over() {
+        log.armies(battle);
+        log.blank();
+        let orders = target_seletion(battle, log);
+        log.blank();
+        if !attack_phase(battle, orders, log) {
+            log.line(|_| "Stalemate; no units were killed this round.".to_owned());
             return None;
         }
     }
-    let remaining_units = [&*army1,&*army2]
-        .iter()
-        .flat_map(|&a| a)
-        .map(|g| g.unit_count)
-        .sum();
-    Some(remaining_units)
+    log.armies(battle);
+    Some(battle.remaining_units())
 }
 
 // Resolve the attacks.
-fn attack_phase(a: (&mut Army, Vec<Order>), b: (&mut Army, Vec<Order>)) -> bool {
+fn attack_phase(battle: &mut Battle, mut orders: Vec<Order>, log: &mut Log) -> bool {
 
-    let (mut army1, orders1) = a;
-    let (mut army2, orders2) = b;
-
-    // associate orders with army identifier so that
-    // we can combine and sort them together:
-    let g1 = repeat(1).zip(orders1);
-    let g2 = repeat(2).zip(orders2);
-
-    // Get group given army and group ID:
-    fn get_group<'a>(army: &'a Army, group_id: usize) -> Option<&'a Group> {
-        army.iter().find(|g| g.id == group_id)
-    };
-    fn get_group_mut<'a>(army: &'a mut Army, group_id: usize) -> Option<&'a mut Group> {
-        army.iter_mut().find(|g| g.id == group_id)
-    };
+    let groups = &mut battle.groups;
 
-    // Sort orders by initiative in prep for attack:
-    let mut orders: Vec<(usize,Order)> = g1.chain(g2).collect(); 
-    orders.sort_by_key(|&(army_ident,(group_id,_))| {
-        let army = if army_ident == 1 { &army1 } else { &army2 };
-        let group = get_group(army,group_id).unwrap();
-        Reverse(group.initiative)
-    });
+    // Sort orders by initiative in prep for attack (every group
+    // that was given an order is still around at this point):
+    orders.sort_by_key(|&(group_id,_)| Reverse(groups[&group_id].initiative));
 
-    // Perform each attack:
+    // Perform each attack. Groups killed earlier in this phase stay around
+    // until the end of it, so that we can see that they've died:
     let mut successful_attack = false;
-    for (army_ident,(group_id,target_id)) in orders {
-        let (attackers,defenders) = if army_ident == 1 {
-            (&army1,&mut army2)
-        } else {
-            (&army2, &mut army1)
-        };
-        let group = match get_group(attackers, group_id) {
-            Some(g) => g,
-            None => { continue } //group may have died
-        };
-        let target = match get_group_mut(defenders, target_id) {
-            Some(g) => g,
-            None => panic!("Target expected with ID {}", target_id)
-        };
-        if group.attack(target) {
+    for (group_id,target_id) in orders {
+        let group = &groups[&group_id];
+        if group.is_dead() {
+            // Killed by a group with a higher initiative; dead groups don't attack:
+            continue
+        }
+        let target = &groups[&target_id];
+        if target.is_dead() {
+            // Each target is only picked once, so nobody else should have
+            // killed it, but dead groups can't be attacked either way:
+            continue
+        }
+        let damage = target.attack_damage_from(group);
+
+        let target = groups.get_mut(&target_id).unwrap();
+        let killed = target.damage_by(damage);
+        if killed > 0 {
             successful_attack = true;
         }
+        log.line(|names| format!("{} group {} attacks {}, killing {} unit{}",
+            names[group_id.army], group_id.number, defending(names, target_id), killed,
+            if killed == 1 { "" } else { "s" }));
     }
 
     // Remove dead groups:
-    for army in &mut [&mut army1, &mut army2] {
-        for idx in (0..army.len()).rev() {
-            if army[idx].is_dead() {
-                army.swap_remove(idx);
-            }
-        }
-    }
+    groups.retain(|_,g| !g.is_dead());
 
     // Return false if no attack was successful (ie we are stuck):
     successful_attack
 }
 
-type Order = (usize,usize);
+type Order = (GroupId,GroupId);
 
 // Return a vector of attacker ID to defender ID (if it can attack anything).
-// The vector is ordered by which attack should happen first from this side.
-fn target_seletion(attackers: &[Group], defenders: &[Group]) -> Vec<Order> {
-    let mut choose_order: Vec<&Group> = attackers
-        .iter()
-        .collect();
+// Every group from every army picks in turn, and can pick any group in an army
+// hostile to its own, but each group can only be picked by one attacker.
+fn target_seletion(battle: &Battle, log: &mut Log) -> Vec<Order> {
+    let mut choose_order: Vec<&Group> = battle.groups.values().collect();
 
     choose_order.sort_by_key(|a| Reverse((a.effective_power(), a.initiative)));
 
-    let mut available_defenders: Vec<&Group> = defenders
-        .iter()
-        .collect();
+    // (Groups are ordered by army and then number within that army):
+    let mut available_defenders: Vec<&Group> = battle.groups.values().collect();
+
+    // The puzzle's example lists each army's choices together, with the
+    // infection's first, so note them by army and list later armies first:
+    let mut army_logs: Vec<Log> = battle.names.iter().map(|_| log.alongside()).collect();
 
     let mut targets_by_id = Vec::new();
     for group in choose_order {
-        let target = pick_best_target(group, &available_defenders);
+        let army_ident = group.id.army;
+        let options: Vec<&Group> = available_defenders
+            .iter()
+            .filter(|d| battle.hostile(army_ident, d.id.army))
+            .cloned()
+            .collect();
+
+        // Note the damage we could do to each group we could pick:
+        for d in &options {
+            let damage = d.attack_damage_from(group);
+            if damage > 0 {
+                army_logs[army_ident].line(|names| format!("{} group {} would deal {} {} damage",
+                    names[army_ident], group.id.number, defending(names, d.id), damage));
+            }
+        }
+
+        let target = pick_best_target(group, &options);
         if let Some(target_id) = target {
-            available_defenders
-                .iter()
-                .position(|d| d.id == target_id)
-                .map(|idx| available_defenders.swap_remove(idx));
+            available_defenders.retain(|d| d.id != target_id);
             targets_by_id.push((group.id, target_id));
         }
     }
+
+    for army_log in army_logs.into_iter().rev() {
+        log.append(army_log);
+    }
     targets_by_id
 }
 
 // Pick the target we'd deal most damage to, breaking tie
 // by effective power and then initiative:
-fn pick_best_target(attacker: &Group, defenders: &[&Group]) -> Option<usize> {
+fn pick_best_target(attacker: &Group, defenders: &[&Group]) -> Option<GroupId> {
     defenders
         .iter()
         .filter(|d| {
@@ -169,79 +304,149 @@ fn pick_best_target(attacker: &Group, defenders: &[&Group]) -> Option<usize> {
         .map(|d| d.id)
 }
 
-type Army = Vec<Group>;
-
-fn parse_armies(s: &str) -> Result<(Army,Army)> {
+// Parse the armies, and which of them are allied with each other:
+fn parse_battle(s: &str) -> Result<Battle> {
 
     lazy_static!{
         static ref re: Regex = Regex::new(
-            r"^(\d+) units each with (\d+) hit points (?:\(([^)]+)\) )?with an attack that does (\d+) (\w+) damage at initiative (\d+)$"
+            r"^(\d+) units each with (\d+) hit points (?:\(([^)]+)\) )?with an attack that does (\d+) (.+?) damage at initiative (\d+)$"
+        ).unwrap();
+        static ref alliance_re: Regex = Regex::new(
+            r"^(.+) (?:is|are) allied with (.+)$"
         ).unwrap();
     }
 
-    let mut armies = vec![];
-    let mut groups = vec![];
-    let mut next_id = 1;
+    let mut names: Vec<String> = vec![];
+    let mut alliances = vec![];
+    let mut groups = BTreeMap::new();
+    let mut numbers = vec![];
+
+    // Weapons can be called anything; we just give each new one an ID:
+    let mut weapons = HashMap::new();
+    let mut weapon = |name: &str| {
+        let next = Weapon(weapons.len());
+        *weapons.entry(name.to_owned()).or_insert(next)
+    };
 
     for line in s.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
 
-        // New army; save last if present and start new army:
-        if line.ends_with(":") {
-            if !groups.is_empty() {
-                armies.push(groups);
-                groups = vec![];
+        // New army; start adding groups to it:
+        if let Some(name) = line.strip_suffix(':') {
+            if names.iter().any(|n| n == name) {
+                return Err(err!("Army '{}' is given more than once", name));
             }
+            names.push(name.to_owned());
+            numbers.push(0);
+            continue;
+        }
+
+        // Two armies that won't attack each other:
+        if let Some(caps) = alliance_re.captures(line) {
+            alliances.push((caps[1].to_owned(), caps[2].to_owned()));
             continue;
         }
 
         // Units in army; parse and add to army:
-        let caps = re.captures(line).ok_or(err!("Line not a valid unit: '{}'", line))?;
+        let caps = re.captures(line).ok_or_else(|| err!("Line not a valid unit: '{}'", line))?;
+        let army_ident = names.len().checked_sub(1)
+            .ok_or_else(|| err!("Unit is not in an army: '{}'", line))?;
         let get = |n| caps.get(n).unwrap().as_str();
         let unit_count = get(1).parse()?;
         let unit_hp = get(2).parse()?;
         let attack_damage = get(4).parse()?;
-        let weapon = Weapon::from_str(get(5))?;
+        let weapon_used = weapon(get(5));
         let initiative = get(6).parse()?;
+        if groups.values().any(|g: &Group| g.initiative == initiative) {
+            return Err(err!("More than one group has an initiative of {}", initiative));
+        }
 
         // Parse optional immunities/weaknesses if provided:
         let mut immune_to = Vec::new();
         let mut weak_to = Vec::new();
         if let Some(cs) = caps.get(3) {
             for bit in cs.as_str().split("; ") {
-                let immune_prefix = "immune to ";
-                let weak_prefix = "weak to ";
-                if bit.starts_with(immune_prefix) {
-                    for w in bit.trim_start_matches(immune_prefix).split(", ") {
-                        immune_to.push(Weapon::from_str(w)?);
-                    }
-                }
-                if bit.starts_with(weak_prefix) {
-                    for w in bit.trim_start_matches(weak_prefix).split(", ") {
-                        weak_to.push(Weapon::from_str(w)?);
-                    }
+                if let Some(ws) = bit.strip_prefix("immune to ") {
+                    immune_to.extend(ws.split(", ").map(&mut weapon));
+                } else if let Some(ws) = bit.strip_prefix("weak to ") {
+                    weak_to.extend(ws.split(", ").map(&mut weapon));
+                } else {
+                    return Err(err!("Expected immunities or weaknesses but got '{}'", bit));
                 }
             }
         }
 
-        // Add this group to our army with the next available ID:
-        let id = next_id;
-        next_id += 1;
-        groups.push(Group {
+        // Add this group to its army. Groups are numbered within each army,
+        // and the ID orders them by army and then by that number:
+        numbers[army_ident] += 1;
+        let id = GroupId { army: army_ident, number: numbers[army_ident] };
+        groups.insert(id, Group {
             id, unit_count, unit_hp, immune_to, weak_to,
-            weapon, attack_damage, initiative
+            weapon: weapon_used, attack_damage, initiative
         });
     }
-    armies.push(groups);
 
-    if armies.len() != 2 {
-        return Err(err!("Expected 2 armies but saw {}", armies.len()));
+    if names.len() < 2 {
+        return Err(err!("Expected at least 2 armies but saw {}", names.len()));
+    }
+
+    let mut allied = vec![vec![false; names.len()]; names.len()];
+    for (a, b) in alliances {
+        let find = |name: &str| names.iter().position(|n| n == name)
+            .ok_or_else(|| err!("Army '{}' in alliance not found", name));
+        let (a, b) = (find(&a)?, find(&b)?);
+        allied[a][b] = true;
+        allied[b][a] = true;
+    }
+
+    Ok(Battle { names, allied, groups })
+}
+
+// ### The armies in a fight, and which of them are on the same side ###
+#[derive(Debug,Clone)]
+struct Battle {
+    names: Vec<String>,
+    // allied[a][b] is true if armies a and b won't attack each other:
+    allied: Vec<Vec<bool>>,
+    // The groups still fighting in every army:
+    groups: BTreeMap<GroupId, Group>
+}
+
+impl Battle {
+    fn hostile(&self, a: usize, b: usize) -> bool {
+        a != b && !self.allied[a][b]
+    }
+    fn groups_in(&self, army_ident: usize) -> impl Iterator<Item=&Group> {
+        self.groups.values().filter(move |g| g.id.army == army_ident)
+    }
+    fn has_groups(&self, army_ident: usize) -> bool {
+        self.groups_in(army_ident).next().is_some()
+    }
+    // The fight is over once no two armies left will attack each other:
+    fn is_over(&self) -> bool {
+        let left: Vec<usize> = (0..self.names.len())
+            .filter(|&a| self.has_groups(a))
+            .collect();
+        !left.iter().any(|&a| left.iter().any(|&b| self.hostile(a, b)))
     }
-    Ok((armies.remove(0), armies.remove(0)))
+    fn remaining_units(&self) -> usize {
+        self.groups
+            .values()
+            .map(|g| g.unit_count)
+            .sum()
+    }
+}
+
+// Groups are identified by the army they're in and which group they are
+// within that army (counting from 1):
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+struct GroupId {
+    army: usize,
+    number: usize
 }
 
 #[derive(Debug,Clone)]
 struct Group {
-    id: usize,
+    id: GroupId,
     unit_count: usize,
     unit_hp: usize,
     immune_to: Vec<Weapon>,
@@ -256,52 +461,240 @@ impl Group {
         self.unit_count * self.attack_damage
     }
     fn attack_damage_from(&self, attacker: &Group) -> usize {
-        if self.immune_to.iter().any(|w| *w == attacker.weapon) {
+        if self.immune_to.contains(&attacker.weapon) {
             0
-        } else if self.weak_to.iter().any(|w| *w == attacker.weapon) {
-            attacker.effective_power() * 2 
+        } else if self.weak_to.contains(&attacker.weapon) {
+            attacker.effective_power() * 2
         } else {
             attacker.effective_power()
         }
     }
-    fn damage_by(&mut self, d: usize) -> bool {
-        let units_killed = d / self.unit_hp;
-        if units_killed == 0 {
-            return false
-        } else if units_killed >= self.unit_count {
-            self.unit_count = 0;
-        } else {
-            self.unit_count -= units_killed;
-        }
-        true // was the attack successful; did units die?
-    }
-    fn attack(&self, target: &mut Group) -> bool {
-        let damage = target.attack_damage_from(self);
-        target.damage_by(damage)
+    // Take some damage, returning how many units died:
+    fn damage_by(&mut self, d: usize) -> usize {
+        let units_killed = (d / self.unit_hp).min(self.unit_count);
+        self.unit_count -= units_killed;
+        units_killed
     }
     fn is_dead(&self) -> bool {
         self.unit_count == 0
     }
 }
 
+// A type of damage, identified by the order it first appears in the input:
 #[derive(Debug,Clone,Copy,PartialEq,Eq)]
-enum Weapon {
-    Cold,
-    Slashing,
-    Radiation,
-    Bludgeoning,
-    Fire
+struct Weapon(usize);
+
+// ### A description of a fight, in the style of the puzzle's example ###
+//
+// Lines are only formatted if we're keeping a log, so that fights that
+// aren't logged don't pay for it.
+struct Log {
+    names: Vec<String>,
+    out: Option<String>
 }
 
-impl Weapon {
-    fn from_str(s: &str) -> Result<Weapon> {
-        match s {
-            "cold" => Ok(Cold),
-            "slashing" => Ok(Slashing),
-            "radiation" => Ok(Radiation),
-            "bludgeoning" => Ok(Bludgeoning),
-            "fire" => Ok(Fire),
-            _ => Err(err!("Weapon variant not found for '{}'", s))
+impl Log {
+    fn new(battle: &Battle) -> Log {
+        Log { names: battle.names.clone(), out: Some(String::new()) }
+    }
+    fn none() -> Log {
+        Log { names: Vec::new(), out: None }
+    }
+    // Add a line, which is given the army names to use:
+    fn line(&mut self, line: impl FnOnce(&[String]) -> String) {
+        if let Some(out) = self.out.as_mut() {
+            out.push_str(&line(&self.names));
+            out.push('\n');
         }
     }
-}
\ No newline at end of file
+    // A new, empty log, which is only kept if this one is:
+    fn alongside(&self) -> Log {
+        Log { names: self.names.clone(), out: self.out.as_ref().map(|_| String::new()) }
+    }
+    fn append(&mut self, other: Log) {
+        if let (Some(out), Some(other)) = (self.out.as_mut(), other.out) {
+            out.push_str(&other);
+        }
+    }
+    fn blank(&mut self) {
+        self.line(|_| String::new())
+    }
+    // How many units each group of each army has:
+    fn armies(&mut self, battle: &Battle) {
+        for (army_ident, name) in battle.names.iter().enumerate() {
+            self.line(|_| format!("{}:", name));
+            if !battle.has_groups(army_ident) {
+                self.line(|_| "No groups remain.".to_owned());
+            }
+            for g in battle.groups_in(army_ident) {
+                self.line(|_| format!("Group {} contains {} units", g.id.number, g.unit_count));
+            }
+        }
+    }
+    fn into_string(self) -> Option<String> {
+        self.out
+    }
+}
+
+// The puzzle only has one army to defend against, but with more than that
+// we need to say which army the defending group is in:
+fn defending(names: &[String], id: GroupId) -> String {
+    if names.len() > 2 {
+        format!("defending {} group {}", names[id.army], id.number)
+    } else {
+        format!("defending group {}", id.number)
+    }
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    const EXAMPLE: &str = "
+        Immune System:
+        17 units each with 5390 hit points (weak to radiation, bludgeoning) with an attack that does 4507 fire damage at initiative 2
+        989 units each with 1274 hit points (immune to fire; weak to bludgeoning, slashing) with an attack that does 25 slashing damage at initiative 3
+
+        Infection:
+        801 units each with 4706 hit points (weak to radiation) with an attack that does 116 bludgeoning damage at initiative 1
+        4485 units each with 2961 hit points (immune to radiation; weak to fire, cold) with an attack that does 12 slashing damage at initiative 4
+    ";
+
+    #[test]
+    fn example_log() {
+        // The worked example in the puzzle description:
+        let puzzle = include_str!("puzzle.txt");
+        let start = puzzle.find("would take place:\n\n").unwrap() + "would take place:\n\n".len();
+        let end = puzzle.find("In the example above").unwrap();
+        let expected = &puzzle[start..end];
+
+        let mut battle = parse_battle(EXAMPLE).unwrap();
+        let mut log = Log::new(&battle);
+        assert_eq!(fight(&mut battle, &mut log), Some(5216));
+        assert_eq!(log.into_string().unwrap(), expected);
+    }
+
+    #[test]
+    fn example_boost() {
+        let battle = parse_battle(EXAMPLE).unwrap();
+        let mut search = BoostSearch::new(&battle);
+        assert_eq!(search.outcome(1570), Outcome::Won(51));
+        assert_eq!(search.smallest_win(20), Some((1570, 51)));
+    }
+
+    #[test]
+    fn allies_dont_fight() {
+        let input = format!("{}\nImmune System is allied with Infection", EXAMPLE);
+        let mut battle = parse_battle(&input).unwrap();
+        assert!(battle.is_over());
+        assert_eq!(fight(&mut battle, &mut Log::none()), Some(17 + 989 + 801 + 4485));
+
+        let input = format!("{}\nImmune System is allied with Reinforcements", EXAMPLE);
+        assert!(parse_battle(&input).is_err());
+    }
+
+    #[test]
+    fn three_armies() {
+        // Reinforcements for the immune system, with a new kind of weapon:
+        let input = format!("{}
+            Reinforcements:
+            2000 units each with 3000 hit points (immune to fire) with an attack that does 500 hot coals damage at initiative 5
+
+            Reinforcements are allied with Immune System
+        ", EXAMPLE);
+        let mut battle = parse_battle(&input).unwrap();
+        assert_eq!(battle.names.len(), 3);
+        assert!(!battle.hostile(0, 2));
+        assert!(battle.hostile(1, 2));
+
+        let mut log = Log::new(&battle);
+        let remaining = fight(&mut battle, &mut log).unwrap();
+        assert!(!battle.has_groups(1));
+        assert!(battle.has_groups(2));
+        assert_eq!(remaining, 287 + 1887);
+        assert!(log.into_string().unwrap().contains("would deal defending Infection group"));
+    }
+
+    #[test]
+    fn target_picked_once() {
+        // Both allies would pick the only infection group, but only the
+        // first of them to choose gets to, so the other sits this round out:
+        let input = "
+            Immune System:
+            10 units each with 10 hit points with an attack that does 100 fire damage at initiative 3
+            Reinforcements:
+            10 units each with 10 hit points with an attack that does 100 cold damage at initiative 2
+            Infection:
+            5 units each with 10 hit points with an attack that does 1 fire damage at initiative 1
+            Immune System is allied with Reinforcements
+        ";
+        let mut battle = parse_battle(input).unwrap();
+        let mut log = Log::new(&battle);
+        assert_eq!(fight(&mut battle, &mut log), Some(20));
+        let log = log.into_string().unwrap();
+        assert!(log.contains("Immune System group 1 attacks defending Infection group 1, killing 5 units"));
+        assert!(!log.contains("Reinforcements group 1 would deal"));
+        assert!(!log.contains("Reinforcements group 1 attacks"));
+    }
+
+    #[test]
+    fn duplicate_initiative() {
+        let input = EXAMPLE.replace("at initiative 4", "at initiative 3");
+        assert!(parse_battle(&input).is_err());
+    }
+
+    // The header and group lines of each army in some input:
+    fn army_lines(input: &str) -> Vec<(String, Vec<String>)> {
+        let mut armies: Vec<(String, Vec<String>)> = vec![];
+        for line in input.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            if line.ends_with(':') {
+                armies.push((line.to_owned(), vec![]));
+            } else {
+                armies.last_mut().unwrap().1.push(line.to_owned());
+            }
+        }
+        armies
+    }
+
+    // Run a fight, returning the outcome and how many units are left in
+    // each group (which we know by initiative, since that's unique):
+    fn outcome_of(input: &str, boost: usize) -> (Outcome, Vec<(usize, usize)>) {
+        let battle = parse_battle(input).unwrap();
+        let outcome = BoostSearch::new(&battle).outcome(boost);
+
+        let mut battle = battle.clone();
+        battle.groups.values_mut()
+            .filter(|g| g.id.army == 0)
+            .for_each(|g| g.attack_damage += boost);
+        fight(&mut battle, &mut Log::none());
+        let mut left: Vec<(usize, usize)> = battle.groups.values()
+            .map(|g| (g.initiative, g.unit_count))
+            .collect();
+        left.sort();
+        (outcome, left)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn group_order_doesnt_matter(
+            immune in Just(army_lines(include_str!("input.txt"))[0].1.clone()).prop_shuffle(),
+            infection in Just(army_lines(include_str!("input.txt"))[1].1.clone()).prop_shuffle(),
+            boost in 0..60usize
+        ) {
+            let armies = army_lines(include_str!("input.txt"));
+            let shuffled = format!("{}\n{}\n{}\n{}",
+                armies[0].0, immune.join("\n"),
+                armies[1].0, infection.join("\n"));
+            prop_assert_eq!(
+                outcome_of(&shuffled, boost),
+                outcome_of(include_str!("input.txt"), boost)
+            );
+        }
+    }
+}
@@ -1,72 +1,78 @@
 use std::result;
 use std::error::Error;
-use std::collections::{ HashMap, HashSet };
+use manhattan::cluster::clusters;
 
+macro_rules! err { ($($tt:tt)*) => { Box::<dyn $crate::Error>::from(format!($($tt)*)) } }
 type Result<T> = result::Result<T, Box<dyn Error + 'static>>;
 
+// Usage: day25 <input> [--within N] [--list]
+//
+// Every line of the input is a point with the same number of comma separated
+// coordinates (4 for the puzzle, but there can be any number, so they're kept
+// as plain lists of coordinates rather than a fixed size `Point`). Points are in
+// the same constellation if they're within 3 of each other (or N if given).
+// With --list, the size, bounds and members (by line) of every constellation
+// are printed too.
 fn main() -> Result<()> {
 
     let filename = std::env::args().nth(1).expect("need puzzle input");
-    let coords: Vec<Coords> = std::fs::read_to_string(filename)
-        .expect("can't open file")
-        .lines()
-        .map(parse_coords)
-        .collect::<Result<_>>()?;
+    let input = std::fs::read_to_string(filename)?;
+    let opts = parse_opts(std::env::args().skip(2))?;
 
-    // What coords is each coord next to:
-    let mut adjacent = HashMap::new();
-    for c in &coords {
-        let mut n = Vec::new();
-        for c2 in &coords {
-            if distance(c, c2) <= 3 {
-                n.push(*c2);
-            }
-        }
-        if n.len() > 0 {
-            adjacent.insert(*c, n);
-        }
-    }
+    let rows = parse_rows(&input)?;
+    let points: Vec<Vec<i64>> = rows.iter().map(|(_,row)| row.clone()).collect();
+
+    let constellations = clusters(&points, opts.within);
+    println!("Star 1: {}", constellations.len());
 
-    // Recursively build up constellation counts by
-    // following these links:
-    let mut visited = HashSet::new();
-    let mut count = 0;
-    for c in &coords {
-        if !visited.contains(c) {
-            count += 1;
-            visit_neighbours(c, &mut visited, &adjacent);
+    if opts.list {
+        for c in &constellations {
+            let lines: Vec<String> = c.members.iter().map(|&idx| rows[idx].0.to_string()).collect();
+            println!("{} points, from {:?} to {:?}: lines {}", c.size(), c.min, c.max, lines.join(","));
         }
     }
-    println!("Star 1: {}", count);
 
     Ok(())
+}
 
+struct Opts {
+    within: i64,
+    list: bool
 }
 
-fn visit_neighbours(
-    coords: &Coords, 
-    visited: &mut HashSet<Coords>, 
-    adjacent: &HashMap<Coords, Vec<Coords>>
-) {
-    for c in adjacent.get(coords).unwrap_or(&Vec::new()) {
-        if !visited.contains(c) {
-            visited.insert(*c);
-            visit_neighbours(c, visited, adjacent);
+fn parse_opts(mut args: impl Iterator<Item=String>) -> Result<Opts> {
+    let mut opts = Opts { within: 3, list: false };
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--within" => {
+                let v = args.next().ok_or_else(|| err!("Expected a value after '{}'", arg))?;
+                opts.within = v.parse()?;
+                if opts.within < 0 {
+                    return Err(err!("--within should not be negative"));
+                }
+            },
+            "--list" => { opts.list = true },
+            _ => return Err(err!("Unexpected argument '{}'", arg))
         }
     }
+    Ok(opts)
 }
 
-fn parse_coords(s: &str) -> Result<Coords> {
-    let mut cs = [0;4];
-    for (idx,c) in s.split(",").enumerate().take(4) {
-        let n = c.parse()?;
-        cs[idx] = n;
+// Parse every non-empty line into its coordinates, alongside its line number,
+// checking that every line has as many coordinates as the first:
+fn parse_rows(s: &str) -> Result<Vec<(usize, Vec<i64>)>> {
+    let mut rows: Vec<(usize, Vec<i64>)> = Vec::new();
+    for (idx, line) in s.lines().enumerate().filter(|(_,l)| !l.trim().is_empty()) {
+        let row = line.split(',')
+            .map(|c| c.trim().parse::<i64>())
+            .collect::<result::Result<Vec<_>,_>>()
+            .map_err(|e| err!("Line {} ('{}') is not a valid point: {}", idx + 1, line, e))?;
+        if let Some((first, expected)) = rows.first().map(|(n,r)| (*n, r.len())) {
+            if row.len() != expected {
+                return Err(err!("Line {} has {} coordinates, but line {} has {}", idx + 1, row.len(), first, expected));
+            }
+        }
+        rows.push((idx + 1, row));
     }
-    Ok(cs)
+    Ok(rows)
 }
-
-type Coords = [i64;4];
-
-fn distance(a: &Coords, b: &Coords) -> i64 {
-    a.iter().zip(b).map(|(c1,c2)| (c1-c2).abs()).sum()
-}
\ No newline at end of file
@@ -0,0 +1,424 @@
+// A 2D grid shared between the days that need one. Coordinates are signed, so
+// stepping off the edge of a grid is never an underflow; it just lands on a
+// point that the grid doesn't (yet) contain.
+use std::collections::HashMap;
+use std::ops::Add;
+
+// ### An X,Y location (y is first so that points sort in reading order) ###
+#[derive(Debug,PartialOrd,Ord,PartialEq,Eq,Clone,Copy,Hash,Default)]
+pub struct Point {
+    pub y: i64,
+    pub x: i64
+}
+impl Point {
+    pub fn new(x: i64, y: i64) -> Point {
+        Point { x, y }
+    }
+    // The 4 points above, left, right and below this one, in reading order:
+    pub fn neighbours4(self) -> Vec<Point> {
+        let Point { x, y } = self;
+        vec![ Point::new(x,y-1), Point::new(x-1,y), Point::new(x+1,y), Point::new(x,y+1) ]
+    }
+    // All 8 points surrounding this one, in reading order:
+    pub fn neighbours8(self) -> Vec<Point> {
+        let Point { x, y } = self;
+        vec![ Point::new(x-1,y-1), Point::new(x,y-1), Point::new(x+1,y-1),
+              Point::new(x-1,y),                      Point::new(x+1,y),
+              Point::new(x-1,y+1), Point::new(x,y+1), Point::new(x+1,y+1) ]
+    }
+}
+impl Add for Point {
+    type Output = Point;
+    fn add(self, other: Point) -> Point {
+        Point::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+// ### A rectangular region of points ###
+#[derive(Debug,PartialEq,Eq,Clone,Copy,Hash)]
+pub struct Rect {
+    pub x: i64,
+    pub y: i64,
+    pub width: usize,
+    pub height: usize
+}
+impl Rect {
+    pub fn new(x: i64, y: i64, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+    // The smallest rect containing a single point:
+    pub fn from_point(p: Point) -> Rect {
+        Rect::new(p.x, p.y, 1, 1)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+    pub fn right(&self) -> i64 {
+        self.x + self.width as i64
+    }
+    pub fn bottom(&self) -> i64 {
+        self.y + self.height as i64
+    }
+    pub fn contains(&self, p: Point) -> bool {
+        p.x >= self.x && p.x < self.right() && p.y >= self.y && p.y < self.bottom()
+    }
+    // The smallest rect containing both this one and the point given:
+    pub fn including(&self, p: Point) -> Rect {
+        if self.is_empty() {
+            return Rect::from_point(p);
+        }
+        let x = self.x.min(p.x);
+        let y = self.y.min(p.y);
+        let right = self.right().max(p.x + 1);
+        let bottom = self.bottom().max(p.y + 1);
+        Rect::new(x, y, (right - x) as usize, (bottom - y) as usize)
+    }
+    // The overlap of two rects (which may be empty):
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right()).max(x);
+        let bottom = self.bottom().min(other.bottom()).max(y);
+        Rect::new(x, y, (right - x) as usize, (bottom - y) as usize)
+    }
+    // Every point in the rect, in reading order:
+    pub fn points(self) -> impl Iterator<Item=Point> {
+        (self.y..self.bottom()).flat_map(move |y| {
+            (self.x..self.right()).map(move |x| Point::new(x,y))
+        })
+    }
+}
+
+// ### A dense grid, storing a value for every point within its bounds ###
+#[derive(Debug,PartialEq,Eq,Clone,Hash)]
+pub struct Grid<T> {
+    bounds: Rect,
+    fill: T,
+    values: Vec<T>
+}
+impl <T: Clone> Grid<T> {
+    // A grid covering the bounds given, with every value set to `fill`:
+    pub fn new(bounds: Rect, fill: T) -> Grid<T> {
+        Grid {
+            bounds,
+            values: vec![fill.clone(); bounds.width * bounds.height],
+            fill
+        }
+    }
+    // Parse some ASCII art into a grid with its top left at 0,0. Any bytes that
+    // `parse` returns None for are left as `fill`:
+    pub fn from_ascii(s: &str, fill: T, mut parse: impl FnMut(u8) -> Option<T>) -> Grid<T> {
+        let width = s.lines().map(|l| l.len()).max().unwrap_or(0);
+        let height = s.lines().count();
+        let mut grid = Grid::new(Rect::new(0, 0, width, height), fill);
+        for (y, line) in s.lines().enumerate() {
+            for (x, byte) in line.bytes().enumerate() {
+                if let Some(value) = parse(byte) {
+                    grid.set(Point::new(x as i64, y as i64), value);
+                }
+            }
+        }
+        grid
+    }
+    // Set a value, growing the grid (and filling any new space with `fill`)
+    // if the point lies outside of the current bounds:
+    pub fn set(&mut self, p: Point, value: T) {
+        if !self.bounds.contains(p) {
+            self.grow_to(self.bounds.including(p));
+        }
+        let idx = self.index(p).unwrap();
+        self.values[idx] = value;
+    }
+    // Grow the grid so that it covers at least the bounds given:
+    pub fn grow_to(&mut self, bounds: Rect) {
+        let mut new_bounds = self.bounds;
+        if !bounds.is_empty() {
+            new_bounds = new_bounds
+                .including(Point::new(bounds.x, bounds.y))
+                .including(Point::new(bounds.right() - 1, bounds.bottom() - 1));
+        }
+        if new_bounds == self.bounds {
+            return;
+        }
+        let mut grid = Grid::new(new_bounds, self.fill.clone());
+        for (p, value) in self.iter() {
+            let idx = grid.index(p).unwrap();
+            grid.values[idx] = value.clone();
+        }
+        *self = grid;
+    }
+    // Build a summed-area table from this grid, to answer rect sums in O(1):
+    pub fn summed_area(&self) -> SummedArea where T: Copy + Into<i64> {
+        SummedArea::new(self)
+    }
+    // Apply a function to every value to produce a new grid of the same size:
+    pub fn map<U: Clone>(&self, mut f: impl FnMut(Point, &T) -> U, fill: U) -> Grid<U> {
+        Grid {
+            bounds: self.bounds,
+            values: self.iter().map(|(p,v)| f(p,v)).collect(),
+            fill
+        }
+    }
+}
+impl <T> Grid<T> {
+    pub fn bounds(&self) -> Rect {
+        self.bounds
+    }
+    pub fn width(&self) -> usize {
+        self.bounds.width
+    }
+    pub fn height(&self) -> usize {
+        self.bounds.height
+    }
+    pub fn contains(&self, p: Point) -> bool {
+        self.bounds.contains(p)
+    }
+    pub fn get(&self, p: Point) -> Option<&T> {
+        self.index(p).map(|idx| &self.values[idx])
+    }
+    pub fn get_mut(&mut self, p: Point) -> Option<&mut T> {
+        self.index(p).map(move |idx| &mut self.values[idx])
+    }
+    // Every point and value in the grid, in reading order:
+    pub fn iter(&self) -> impl Iterator<Item=(Point,&T)> {
+        self.bounds.points().zip(self.values.iter())
+    }
+    pub fn values(&self) -> impl Iterator<Item=&T> {
+        self.values.iter()
+    }
+    // The points and values in some region of the grid (clipped to its bounds):
+    pub fn region(&self, rect: Rect) -> impl Iterator<Item=(Point,&T)> {
+        rect.intersection(&self.bounds)
+            .points()
+            .map(move |p| (p, &self.values[self.index(p).unwrap()]))
+    }
+    // The 4-connected neighbours of a point that lie within the grid:
+    pub fn neighbours4(&self, p: Point) -> impl Iterator<Item=(Point,&T)> {
+        p.neighbours4().into_iter().filter_map(move |n| self.get(n).map(|v| (n,v)))
+    }
+    // The 8-connected neighbours of a point that lie within the grid:
+    pub fn neighbours8(&self, p: Point) -> impl Iterator<Item=(Point,&T)> {
+        p.neighbours8().into_iter().filter_map(move |n| self.get(n).map(|v| (n,v)))
+    }
+    // Draw the grid out, one line per row:
+    pub fn render(&self, mut f: impl FnMut(Point, &T) -> char) -> String {
+        render_rect(self.bounds, |p| f(p, self.get(p).unwrap()))
+    }
+    fn index(&self, p: Point) -> Option<usize> {
+        if !self.bounds.contains(p) {
+            return None;
+        }
+        let x = (p.x - self.bounds.x) as usize;
+        let y = (p.y - self.bounds.y) as usize;
+        Some(y * self.bounds.width + x)
+    }
+}
+
+// ### A summed-area table; each entry is the sum of everything above and left of it ###
+#[derive(Debug,PartialEq,Eq,Clone)]
+pub struct SummedArea {
+    bounds: Rect,
+    sums: Vec<i64>
+}
+impl SummedArea {
+    pub fn new<T: Copy + Into<i64>>(grid: &Grid<T>) -> SummedArea {
+        let bounds = grid.bounds();
+        let stride = bounds.width + 1;
+        // Pad with a row and column of zeroes to avoid edge cases when querying:
+        let mut sums = vec![0; stride * (bounds.height + 1)];
+        for y in 0..bounds.height {
+            let mut row_sum = 0;
+            for x in 0..bounds.width {
+                row_sum += grid.values[y * bounds.width + x].into();
+                sums[(y+1) * stride + x+1] = sums[y * stride + x+1] + row_sum;
+            }
+        }
+        SummedArea { bounds, sums }
+    }
+    pub fn bounds(&self) -> Rect {
+        self.bounds
+    }
+    // Sum every value in some rect of the original grid (clipped to its bounds):
+    pub fn sum(&self, rect: Rect) -> i64 {
+        let rect = rect.intersection(&self.bounds);
+        if rect.is_empty() {
+            return 0;
+        }
+        let stride = self.bounds.width + 1;
+        let left = (rect.x - self.bounds.x) as usize;
+        let top = (rect.y - self.bounds.y) as usize;
+        let right = left + rect.width;
+        let bottom = top + rect.height;
+        self.sums[bottom * stride + right]
+            - self.sums[top * stride + right]
+            - self.sums[bottom * stride + left]
+            + self.sums[top * stride + left]
+    }
+}
+
+// ### A sparse grid, storing values only at the points they are set ###
+#[derive(Debug,PartialEq,Eq,Clone)]
+pub struct SparseGrid<T> {
+    bounds: Rect,
+    values: HashMap<Point,T>
+}
+impl <T> SparseGrid<T> {
+    pub fn new() -> SparseGrid<T> {
+        SparseGrid {
+            bounds: Rect::new(0, 0, 0, 0),
+            values: HashMap::new()
+        }
+    }
+    // Parse some ASCII art into a grid with its top left at 0,0. Only
+    // the bytes that `parse` returns a value for are stored:
+    pub fn from_ascii(s: &str, mut parse: impl FnMut(u8) -> Option<T>) -> SparseGrid<T> {
+        let mut grid = SparseGrid::new();
+        for (y, line) in s.lines().enumerate() {
+            for (x, byte) in line.bytes().enumerate() {
+                if let Some(value) = parse(byte) {
+                    grid.set(Point::new(x as i64, y as i64), value);
+                }
+            }
+        }
+        grid
+    }
+    // The smallest rect containing every value that has ever been set:
+    pub fn bounds(&self) -> Rect {
+        self.bounds
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    pub fn contains(&self, p: Point) -> bool {
+        self.values.contains_key(&p)
+    }
+    pub fn get(&self, p: Point) -> Option<&T> {
+        self.values.get(&p)
+    }
+    pub fn get_mut(&mut self, p: Point) -> Option<&mut T> {
+        self.values.get_mut(&p)
+    }
+    // Set a value, returning whatever was there before:
+    pub fn set(&mut self, p: Point, value: T) -> Option<T> {
+        self.bounds = self.bounds.including(p);
+        self.values.insert(p, value)
+    }
+    pub fn remove(&mut self, p: Point) -> Option<T> {
+        self.values.remove(&p)
+    }
+    // Every point and value in the grid, in no particular order:
+    pub fn iter(&self) -> impl Iterator<Item=(Point,&T)> {
+        self.values.iter().map(|(&p,v)| (p,v))
+    }
+    pub fn points(&self) -> impl Iterator<Item=Point> + '_ {
+        self.values.keys().cloned()
+    }
+    pub fn values(&self) -> impl Iterator<Item=&T> {
+        self.values.values()
+    }
+    // The points and values set in some region of the grid, in reading order:
+    pub fn region(&self, rect: Rect) -> impl Iterator<Item=(Point,&T)> {
+        rect.intersection(&self.bounds)
+            .points()
+            .filter_map(move |p| self.get(p).map(|v| (p,v)))
+    }
+    // The 4-connected neighbours of a point that have values set:
+    pub fn neighbours4(&self, p: Point) -> impl Iterator<Item=(Point,&T)> {
+        p.neighbours4().into_iter().filter_map(move |n| self.get(n).map(|v| (n,v)))
+    }
+    // The 8-connected neighbours of a point that have values set:
+    pub fn neighbours8(&self, p: Point) -> impl Iterator<Item=(Point,&T)> {
+        p.neighbours8().into_iter().filter_map(move |n| self.get(n).map(|v| (n,v)))
+    }
+    // Draw the grid out, one line per row of its bounds:
+    pub fn render(&self, f: impl FnMut(Point, Option<&T>) -> char) -> String {
+        self.render_region(self.bounds, f)
+    }
+    // Draw some region of the grid out, whether or not anything is set in it:
+    pub fn render_region(&self, rect: Rect, mut f: impl FnMut(Point, Option<&T>) -> char) -> String {
+        render_rect(rect, |p| f(p, self.get(p)))
+    }
+}
+impl <T> Default for SparseGrid<T> {
+    fn default() -> SparseGrid<T> {
+        SparseGrid::new()
+    }
+}
+
+fn render_rect(rect: Rect, mut f: impl FnMut(Point) -> char) -> String {
+    let mut out = String::with_capacity((rect.width + 1) * rect.height);
+    for y in rect.y..rect.bottom() {
+        for x in rect.x..rect.right() {
+            out.push(f(Point::new(x,y)));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn points_sort_in_reading_order() {
+        let mut ps = vec![Point::new(1,1), Point::new(2,0), Point::new(0,1)];
+        ps.sort();
+        assert_eq!(ps, vec![Point::new(2,0), Point::new(0,1), Point::new(1,1)]);
+    }
+
+    #[test]
+    fn grid_grows_to_fit() {
+        let mut grid = Grid::new(Rect::new(0, 0, 2, 2), 0);
+        grid.set(Point::new(1,1), 1);
+        grid.set(Point::new(-1,3), 2);
+        assert_eq!(grid.bounds(), Rect::new(-1, 0, 3, 4));
+        assert_eq!(grid.get(Point::new(1,1)), Some(&1));
+        assert_eq!(grid.get(Point::new(-1,3)), Some(&2));
+        assert_eq!(grid.get(Point::new(0,3)), Some(&0));
+        assert_eq!(grid.get(Point::new(2,0)), None);
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        let art = "#.#\n.#.\n#.#\n";
+        let grid = Grid::from_ascii(art, false, |b| Some(b == b'#'));
+        assert_eq!(grid.render(|_,&v| if v { '#' } else { '.' }), art);
+        assert_eq!(grid.neighbours4(Point::new(1,1)).filter(|(_,&v)| v).count(), 0);
+        assert_eq!(grid.neighbours8(Point::new(1,1)).filter(|(_,&v)| v).count(), 4);
+        assert_eq!(grid.neighbours8(Point::new(0,0)).count(), 3);
+
+        let sparse = SparseGrid::from_ascii(art, |b| if b == b'#' { Some(()) } else { None });
+        assert_eq!(sparse.len(), 5);
+        assert_eq!(sparse.render(|_,v| if v.is_some() { '#' } else { '.' }), art);
+        assert_eq!(sparse.render_region(Rect::new(-1, 0, 3, 2), |_,v| if v.is_some() { '#' } else { '.' }), ".#.\n..#\n");
+    }
+
+    #[test]
+    fn regions_are_clipped() {
+        let grid = Grid::from_ascii("123\n456\n789", 0, |b| Some(b - b'0'));
+        let sum: u8 = grid.region(Rect::new(1, 1, 5, 5)).map(|(_,v)| v).sum();
+        assert_eq!(sum, 5 + 6 + 8 + 9);
+        assert_eq!(grid.region(Rect::new(5, 5, 2, 2)).count(), 0);
+    }
+
+    #[test]
+    fn summed_area_matches_region_sums() {
+        let mut grid = Grid::new(Rect::new(-2, 3, 7, 5), 0i32);
+        for (n, p) in grid.bounds().points().enumerate() {
+            grid.set(p, (n as i32 * 37) % 11 - 5);
+        }
+        let sums = grid.summed_area();
+        for rect in Rect::new(-3, 2, 9, 7).points().map(|p| Rect::new(p.x, p.y, 3, 2)) {
+            let expected: i32 = grid.region(rect).map(|(_,v)| v).sum();
+            assert_eq!(sums.sum(rect), expected as i64);
+        }
+        assert_eq!(sums.sum(grid.bounds()), grid.values().map(|&v| v as i64).sum::<i64>());
+    }
+}
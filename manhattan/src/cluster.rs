@@ -0,0 +1,271 @@
+// Group points into clusters (the puzzle calls them constellations), where
+// two points are in the same cluster if they're within some distance of each
+// other, or are linked by a chain of points that each are.
+use std::collections::HashMap;
+use crate::distance;
+
+// ### A cluster of points ###
+//
+// Points can have any number of coordinates, so they're given as lists of
+// them rather than as a `Point<N>`, and the bounds are the corners of the
+// smallest box containing every member, like a `BoundingBox` has.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Cluster {
+    // Indexes into the points that were clustered, in ascending order:
+    pub members: Vec<usize>,
+    // The smallest and largest value of each coordinate across the members:
+    pub min: Vec<i64>,
+    pub max: Vec<i64>
+}
+
+impl Cluster {
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+}
+
+// Group points that are within `within` of each other, in order of their first
+// member. Every point must have the same number of coordinates.
+//
+// Points are hashed into cells `within` wide in every direction, so that we
+// only need to compare each point with those in the same or an adjacent cell.
+// There are 3^N cells next to each one, so rather than trying each of them, we
+// walk through the occupied cells one axis at a time, only following those close
+// enough to the point to matter.
+pub fn clusters(points: &[Vec<i64>], within: i64) -> Vec<Cluster> {
+    let coords: Vec<&[i64]> = points.iter().map(|p| &p[..]).collect();
+    group(&coords, within).into_iter().map(|members| {
+        let mut min = points[members[0]].clone();
+        let mut max = min.clone();
+        for &idx in &members[1..] {
+            for (axis, &n) in points[idx].iter().enumerate() {
+                min[axis] = min[axis].min(n);
+                max[axis] = max[axis].max(n);
+            }
+        }
+        Cluster { members, min, max }
+    }).collect()
+}
+
+// The members of each cluster, in order of their first member:
+fn group(points: &[&[i64]], within: i64) -> Vec<Vec<usize>> {
+    let dimensions = points.first().map(|p| p.len()).unwrap_or(0);
+    assert!(points.iter().all(|p| p.len() == dimensions), "points should all have {} coordinates", dimensions);
+
+    // Occupied cells, sorted so that cells sharing their first few coordinates
+    // are next to each other:
+    let cell_size = within.max(1);
+    let cell_of = |p: &[i64]| -> Vec<i64> { p.iter().map(|n| n.div_euclid(cell_size)).collect() };
+    let mut by_cell: HashMap<Vec<i64>, Vec<usize>> = HashMap::new();
+    for (idx, p) in points.iter().enumerate() {
+        by_cell.entry(cell_of(p)).or_default().push(idx);
+    }
+    let mut cells: Vec<(Vec<i64>, Vec<usize>)> = by_cell.into_iter().collect();
+    cells.sort();
+
+    // Each point only looks at cells up to and including its own, so that
+    // every pair of points is only compared once:
+    let mut sets = DisjointSets::new(points.len());
+    for (idx, p) in points.iter().enumerate() {
+        let cell = cell_of(p);
+        let own = cells.binary_search_by(|(c,_)| c.cmp(&cell)).unwrap();
+        let near = Near { cells: &cells, point: p, cell, cell_size };
+        near.visit(0..own + 1, 0, within, &mut |other_cell, others| {
+            for &other in others {
+                if (other_cell < own || other < idx) && distance(p, points[other]) <= within {
+                    sets.union(idx, other);
+                }
+            }
+        });
+    }
+
+    let mut by_root: HashMap<usize, usize> = HashMap::new();
+    let mut out: Vec<Vec<usize>> = Vec::new();
+    for idx in 0..points.len() {
+        let root = sets.find(idx);
+        match by_root.get(&root) {
+            Some(&c) => out[c].push(idx),
+            None => {
+                by_root.insert(root, out.len());
+                out.push(vec![idx]);
+            }
+        }
+    }
+    out
+}
+
+// Finds the occupied cells that could hold points close to some point:
+struct Near<'a> {
+    cells: &'a [(Vec<i64>, Vec<usize>)],
+    point: &'a [i64],
+    cell: Vec<i64>,
+    cell_size: i64
+}
+
+impl <'a> Near<'a> {
+    // Call `f` with the index and members of every cell near enough to the
+    // point. `range` is the cells matching the point's cell, give or take one,
+    // on every axis before `axis`, and `budget` is how much further we can be
+    // from the point before a cell is too far away to matter:
+    fn visit(&self, range: std::ops::Range<usize>, axis: usize, budget: i64, f: &mut impl FnMut(usize, &[usize])) {
+        if axis == self.cell.len() {
+            for idx in range {
+                f(idx, &self.cells[idx].1);
+            }
+            return;
+        }
+        // With one cell left (which happens quickly with lots of axes),
+        // check the rest of its axes in one go:
+        if range.len() == 1 {
+            let (cell, members) = &self.cells[range.start];
+            let cost = (axis..self.cell.len()).try_fold(0, |total, axis| {
+                self.cost(axis, cell[axis]).map(|cost| total + cost)
+            });
+            if cost.is_some_and(|cost| cost <= budget) {
+                f(range.start, members);
+            }
+            return;
+        }
+        let c = self.cell[axis];
+        for value in c - 1 ..= c + 1 {
+            let cost = self.cost(axis, value).unwrap();
+            if cost > budget {
+                continue;
+            }
+            let cells = &self.cells[range.clone()];
+            let start = range.start + cells.partition_point(|(cell,_)| cell[axis] < value);
+            let end = range.start + cells.partition_point(|(cell,_)| cell[axis] <= value);
+            if start < end {
+                self.visit(start..end, axis + 1, budget - cost, f);
+            }
+        }
+    }
+    // How far along some axis the point is from cells with some coordinate on
+    // that axis, or None if they aren't next to the point's cell:
+    fn cost(&self, axis: usize, value: i64) -> Option<i64> {
+        let (p, c) = (self.point[axis], self.cell[axis]);
+        match value - c {
+            -1 => Some(p - c * self.cell_size + 1),
+            0 => Some(0),
+            1 => Some((c + 1) * self.cell_size - p),
+            _ => None
+        }
+    }
+}
+
+// ### A union-find structure over the numbers 0..n ###
+//
+// Sets are merged by size, and paths are halved as we look up the
+// representative of a set, so no operation needs recursion.
+#[derive(Debug,Clone)]
+pub struct DisjointSets {
+    parent: Vec<usize>,
+    size: Vec<usize>
+}
+
+impl DisjointSets {
+    pub fn new(n: usize) -> DisjointSets {
+        DisjointSets { parent: (0..n).collect(), size: vec![1; n] }
+    }
+    // The representative of whichever set some number is in:
+    pub fn find(&mut self, mut n: usize) -> usize {
+        while self.parent[n] != n {
+            self.parent[n] = self.parent[self.parent[n]];
+            n = self.parent[n];
+        }
+        n
+    }
+    // Merge the sets two numbers are in, returning false if they already were:
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+        true
+    }
+    // How many numbers are in the same set as some number:
+    pub fn size_of(&mut self, n: usize) -> usize {
+        let root = self.find(n);
+        self.size[root]
+    }
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Vec<Vec<i64>> {
+        s.split_whitespace()
+            .map(|l| l.split(',').map(|n| n.parse().unwrap()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn examples() {
+        let first = parse("0,0,0,0 3,0,0,0 0,3,0,0 0,0,3,0 0,0,0,3 0,0,0,6 9,0,0,0 12,0,0,0");
+        let cs = clusters(&first, 3);
+        assert_eq!(cs.len(), 2);
+        assert_eq!(cs[0].members, vec![0,1,2,3,4,5]);
+        assert_eq!((cs[0].min.clone(), cs[0].max.clone()), (vec![0,0,0,0], vec![3,3,3,6]));
+        assert_eq!(cs[1].size(), 2);
+
+        let examples = [
+            ("-1,2,2,0 0,0,2,-2 0,0,0,-2 -1,2,0,0 -2,-2,-2,2 3,0,2,-1 -1,3,2,2 -1,0,-1,0 0,2,1,-2 3,0,0,0", 4),
+            ("1,-1,0,1 2,0,-1,0 3,2,-1,0 0,0,3,1 0,0,-1,-1 2,3,-2,0 -2,2,0,0 2,-2,0,-1 1,-1,0,-1 3,2,0,2", 3),
+            ("1,-1,-1,-2 -2,-2,0,1 0,2,1,3 -2,3,-2,1 0,2,3,-2 -1,-1,1,-2 0,-2,-1,0 -2,2,3,-1 1,2,2,0 -1,-2,0,-2", 8)
+        ];
+        for &(points, expected) in &examples {
+            assert_eq!(clusters(&parse(points), 3).len(), expected);
+        }
+    }
+
+    #[test]
+    fn many_dimensions() {
+        // Pseudo-random points in lots of dimensions, compared against
+        // checking every pair of points:
+        let mut seed: u64 = 12345;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as i64
+        };
+        for &(dimensions, range, within) in &[(4, 9, 3), (12, 3, 4), (60, 2, 20), (200, 2, 84)] {
+            let points: Vec<Vec<i64>> = (0..300)
+                .map(|_| (0..dimensions).map(|_| next() % range).collect())
+                .collect();
+            let mut sets = DisjointSets::new(points.len());
+            for a in 0..points.len() {
+                for b in 0..a {
+                    if distance(&points[a], &points[b]) <= within {
+                        sets.union(a, b);
+                    }
+                }
+            }
+            let found = clusters(&points, within);
+            let expected = (0..points.len()).filter(|&n| sets.find(n) == n).count();
+            assert_eq!(found.len(), expected);
+            for c in &found {
+                assert_eq!(sets.size_of(c.members[0]), c.size());
+                assert!(c.members.iter().all(|&m| sets.find(m) == sets.find(c.members[0])));
+            }
+        }
+    }
+
+    #[test]
+    fn disjoint_sets() {
+        let mut sets = DisjointSets::new(5);
+        assert!(sets.union(0, 1));
+        assert!(sets.union(3, 1));
+        assert!(!sets.union(0, 3));
+        assert_eq!(sets.size_of(3), 3);
+        assert_eq!(sets.find(0), sets.find(3));
+        assert_ne!(sets.find(0), sets.find(4));
+    }
+}
@@ -0,0 +1,257 @@
+// Points in any number of dimensions, and the shapes that Manhattan (L1)
+// distance gives rise to; balls (diamonds in 2D, octahedra in 3D) and
+// bounding boxes, along with tests for whether they overlap and queries for
+// the closest point in one to some other point.
+use std::ops::{Add, Index, IndexMut, Sub};
+
+pub mod cluster;
+
+// The Manhattan distance between two lists of coordinates, for when the
+// number of them is only known at runtime (see `Point::distance` otherwise):
+pub fn distance(a: &[i64], b: &[i64]) -> i64 {
+    a.iter().zip(b).map(|(a,b)| (a - b).abs()).sum()
+}
+
+// ### A point in N dimensions ###
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct Point<const N: usize>(pub [i64; N]);
+
+impl <const N: usize> Point<N> {
+    pub fn new(coords: [i64; N]) -> Point<N> {
+        Point(coords)
+    }
+    pub fn origin() -> Point<N> {
+        Point([0; N])
+    }
+    pub fn coords(&self) -> &[i64; N] {
+        &self.0
+    }
+    // The Manhattan distance between two points:
+    pub fn distance(&self, other: &Point<N>) -> i64 {
+        distance(&self.0, &other.0)
+    }
+    // The Manhattan distance from the origin:
+    pub fn norm(&self) -> i64 {
+        self.0.iter().map(|a| a.abs()).sum()
+    }
+    // The point closest to this one from some others, if exactly one is closest:
+    pub fn closest<'a>(&self, points: impl IntoIterator<Item=&'a Point<N>>) -> Option<&'a Point<N>> {
+        let mut best = None;
+        let mut best_distance = i64::MAX;
+        for point in points {
+            let d = point.distance(self);
+            if d == best_distance {
+                best = None;
+            } else if d < best_distance {
+                best_distance = d;
+                best = Some(point);
+            }
+        }
+        best
+    }
+    fn zip_with(&self, other: &Point<N>, f: impl Fn(i64, i64) -> i64) -> Point<N> {
+        let mut out = [0; N];
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = f(self.0[i], other.0[i]);
+        }
+        Point(out)
+    }
+}
+
+impl <const N: usize> Default for Point<N> {
+    fn default() -> Point<N> {
+        Point::origin()
+    }
+}
+
+impl <const N: usize> Add for Point<N> {
+    type Output = Point<N>;
+    fn add(self, other: Point<N>) -> Point<N> {
+        self.zip_with(&other, |a,b| a + b)
+    }
+}
+
+impl <const N: usize> Sub for Point<N> {
+    type Output = Point<N>;
+    fn sub(self, other: Point<N>) -> Point<N> {
+        self.zip_with(&other, |a,b| a - b)
+    }
+}
+
+impl <const N: usize> Index<usize> for Point<N> {
+    type Output = i64;
+    fn index(&self, idx: usize) -> &i64 {
+        &self.0[idx]
+    }
+}
+
+impl <const N: usize> IndexMut<usize> for Point<N> {
+    fn index_mut(&mut self, idx: usize) -> &mut i64 {
+        &mut self.0[idx]
+    }
+}
+
+// ### Every point within some Manhattan distance of a centre ###
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct Ball<const N: usize> {
+    pub centre: Point<N>,
+    pub radius: i64
+}
+
+impl <const N: usize> Ball<N> {
+    pub fn new(centre: Point<N>, radius: i64) -> Ball<N> {
+        Ball { centre, radius }
+    }
+    pub fn contains(&self, point: &Point<N>) -> bool {
+        self.centre.distance(point) <= self.radius
+    }
+    // Do two balls have any point in common?
+    pub fn intersects(&self, other: &Ball<N>) -> bool {
+        self.centre.distance(&other.centre) <= self.radius + other.radius
+    }
+    // Does this ball have any point in common with a box?
+    pub fn intersects_box(&self, bounds: &BoundingBox<N>) -> bool {
+        self.contains(&bounds.closest_to(&self.centre))
+    }
+    // The point in the ball closest to some other point (there may be several
+    // equally close; this moves towards the centre one axis at a time):
+    pub fn closest_to(&self, point: &Point<N>) -> Point<N> {
+        let mut excess = (self.centre.distance(point) - self.radius).max(0);
+        let mut closest = *point;
+        for i in 0..N {
+            let diff = self.centre[i] - closest[i];
+            let step = diff.abs().min(excess);
+            closest[i] += step * diff.signum();
+            excess -= step;
+        }
+        closest
+    }
+    // How far some point is from the ball (0 if it's inside):
+    pub fn distance(&self, point: &Point<N>) -> i64 {
+        (self.centre.distance(point) - self.radius).max(0)
+    }
+    // The smallest box containing the whole ball:
+    pub fn bounds(&self) -> BoundingBox<N> {
+        let r = Point([self.radius; N]);
+        BoundingBox { min: self.centre - r, max: self.centre + r }
+    }
+}
+
+// ### Every point between two corners (inclusive) ###
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct BoundingBox<const N: usize> {
+    pub min: Point<N>,
+    pub max: Point<N>
+}
+
+impl <const N: usize> BoundingBox<N> {
+    pub fn new(min: Point<N>, max: Point<N>) -> BoundingBox<N> {
+        BoundingBox { min, max }
+    }
+    pub fn from_point(point: Point<N>) -> BoundingBox<N> {
+        BoundingBox { min: point, max: point }
+    }
+    // The smallest box containing every point given, if any are:
+    pub fn from_points<'a>(points: impl IntoIterator<Item=&'a Point<N>>) -> Option<BoundingBox<N>> {
+        let mut points = points.into_iter();
+        let first = BoundingBox::from_point(*points.next()?);
+        Some(points.fold(first, |bounds, p| bounds.including(p)))
+    }
+    // The smallest box containing this one and some point:
+    pub fn including(&self, point: &Point<N>) -> BoundingBox<N> {
+        BoundingBox {
+            min: self.min.zip_with(point, i64::min),
+            max: self.max.zip_with(point, i64::max)
+        }
+    }
+    // The smallest box containing this one and another:
+    pub fn union(&self, other: &BoundingBox<N>) -> BoundingBox<N> {
+        self.including(&other.min).including(&other.max)
+    }
+    // The points in both boxes, if there are any:
+    pub fn intersection(&self, other: &BoundingBox<N>) -> Option<BoundingBox<N>> {
+        let bounds = BoundingBox {
+            min: self.min.zip_with(&other.min, i64::max),
+            max: self.max.zip_with(&other.max, i64::min)
+        };
+        if (0..N).all(|i| bounds.min[i] <= bounds.max[i]) { Some(bounds) } else { None }
+    }
+    pub fn intersects(&self, other: &BoundingBox<N>) -> bool {
+        self.intersection(other).is_some()
+    }
+    pub fn contains(&self, point: &Point<N>) -> bool {
+        (0..N).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+    // The point in the box closest to some other point:
+    pub fn closest_to(&self, point: &Point<N>) -> Point<N> {
+        point.zip_with(&self.min, i64::max).zip_with(&self.max, i64::min)
+    }
+    // How far some point is from the box (0 if it's inside):
+    pub fn distance(&self, point: &Point<N>) -> i64 {
+        self.closest_to(point).distance(point)
+    }
+    // How many points wide the box is along some axis:
+    pub fn width(&self, axis: usize) -> i64 {
+        self.max[axis] - self.min[axis] + 1
+    }
+}
+
+// ###############################################
+// # A bunch of tests to help ensure correctness #
+// ###############################################
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        let a = Point([1, -2, 3]);
+        let b = Point([-1, 2, 3]);
+        assert_eq!(a.distance(&b), 6);
+        assert_eq!(a.norm(), 6);
+        assert_eq!(distance(&[1, -2], &[-1, 2]), 6);
+        assert_eq!(a + b, Point([0, 0, 6]));
+        let points = [Point([0,0]), Point([4,0]), Point([0,5])];
+        assert_eq!(Point([1,1]).closest(&points), Some(&points[0]));
+        assert_eq!(Point([2,0]).closest(&points), None);
+    }
+
+    #[test]
+    fn balls() {
+        let a = Ball::new(Point([0,0,0]), 3);
+        let b = Ball::new(Point([4,2,0]), 3);
+        let c = Ball::new(Point([5,2,0]), 3);
+        assert!(a.intersects(&b));
+        assert!(!a.intersects(&c));
+        assert!(a.contains(&Point([1,1,1])));
+        assert!(!a.contains(&Point([1,1,2])));
+
+        let p = Point([10,-4,1]);
+        let closest = a.closest_to(&p);
+        assert!(a.contains(&closest));
+        assert_eq!(closest.distance(&p), a.distance(&p));
+        assert_eq!(a.distance(&p), 12);
+        assert_eq!(a.closest_to(&Point([1,0,0])), Point([1,0,0]));
+    }
+
+    #[test]
+    fn boxes() {
+        let points = [Point([3,-1]), Point([-2,4]), Point([0,0])];
+        let bounds = BoundingBox::from_points(&points).unwrap();
+        assert_eq!(bounds, BoundingBox::new(Point([-2,-1]), Point([3,4])));
+        assert_eq!(bounds.width(0), 6);
+        assert!(bounds.contains(&Point([3,4])));
+        assert_eq!(bounds.closest_to(&Point([10,-10])), Point([3,-1]));
+        assert_eq!(bounds.distance(&Point([10,-10])), 16);
+
+        let other = BoundingBox::new(Point([3,4]), Point([5,5]));
+        assert_eq!(bounds.intersection(&other), Some(BoundingBox::from_point(Point([3,4]))));
+        assert!(!bounds.intersects(&BoundingBox::new(Point([4,0]), Point([5,5]))));
+
+        let ball = Ball::new(Point([6,6]), 2);
+        assert!(!ball.intersects_box(&bounds));
+        assert!(ball.intersects_box(&other));
+        assert_eq!(ball.bounds(), BoundingBox::new(Point([4,4]), Point([8,8])));
+        assert!(BoundingBox::<2>::from_points(&[]).is_none());
+    }
+}